@@ -0,0 +1,181 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub const BASE: &str = "https://api.cloudflare.com/client/v4";
+
+/// The envelope Cloudflare wraps around every v4 API response.
+#[derive(Deserialize)]
+pub struct Response<T> {
+    pub success: bool,
+    #[serde(default)]
+    pub errors: Vec<Message>,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    pub result: Option<T>,
+    pub result_info: Option<ResultInfo>,
+}
+
+#[derive(Deserialize)]
+pub struct ResultInfo {
+    pub page: u32,
+    pub total_pages: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Message {
+    #[serde(default)]
+    pub code: u32,
+    pub message: String,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+/// A request that Cloudflare answered, but refused.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub errors: Vec<Message>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.errors.is_empty() {
+            return write!(f, "Cloudflare API returned HTTP {}", self.status);
+        }
+        write!(f, "Cloudflare API error")?;
+        for (i, err) in self.errors.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { ":" } else { ";" }, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// A request that never got an answer from Cloudflare.
+#[derive(Debug)]
+pub struct NetworkError(pub String);
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// Send `req` (with an optional JSON body) and unwrap the response envelope,
+/// turning transport failures and `success: false` into typed errors.
+pub fn call<T: DeserializeOwned>(
+    req: &mut ureq::Request,
+    body: Option<Value>,
+) -> Result<Response<T>> {
+    let (status, text) = send(req, body)?;
+    let parsed: Response<T> = match serde_json::from_str(&text) {
+        Ok(parsed) => parsed,
+        Err(_) if !(200..300).contains(&status) => {
+            return Err(ApiError {
+                status,
+                errors: Vec::new(),
+            }
+            .into())
+        }
+        Err(err) => return Err(err).context("Unexpected response from Cloudflare API"),
+    };
+
+    for msg in &parsed.messages {
+        eprintln!("{}", msg);
+    }
+    if !parsed.success || !(200..300).contains(&status) {
+        return Err(ApiError {
+            status,
+            errors: parsed.errors,
+        }
+        .into());
+    }
+
+    Ok(parsed)
+}
+
+/// Send `req` to an endpoint that answers in plain text rather than JSON,
+/// such as the zone file export. Errors still come in the usual envelope.
+pub fn call_text(req: &mut ureq::Request) -> Result<String> {
+    let (status, text) = send(req, None)?;
+    if (200..300).contains(&status) {
+        return Ok(text);
+    }
+    let errors = serde_json::from_str::<Response<Value>>(&text)
+        .map(|parsed| parsed.errors)
+        .unwrap_or_default();
+    Err(ApiError { status, errors }.into())
+}
+
+fn send(req: &mut ureq::Request, body: Option<Value>) -> Result<(u16, String)> {
+    let resp = match body {
+        Some(body) => req.send_json(body),
+        None => req.call(),
+    };
+    if let Some(err) = resp.synthetic_error() {
+        return Err(NetworkError(err.to_string()).into());
+    }
+
+    let status = resp.status();
+    let text = resp
+        .into_string()
+        .map_err(|err| NetworkError(err.to_string()))?;
+    Ok((status, text))
+}
+
+/// Print the request that would be sent, for `--dry-run`. The token is
+/// never shown.
+pub fn print_request(method: &str, url: &str, body: Option<&Value>) -> Result<()> {
+    println!("{} {}", method, url);
+    println!("Authorization: Bearer <redacted>");
+    if let Some(body) = body {
+        println!("Content-Type: application/json");
+        println!("{}", serde_json::to_string_pretty(body)?);
+    }
+    println!();
+    Ok(())
+}
+
+/// GET every page of a list endpoint, `per_page` results at a time.
+pub fn paginate<T: DeserializeOwned>(
+    url: &str,
+    token: &str,
+    query: &[(&str, &str)],
+    per_page: u32,
+) -> Result<Vec<T>> {
+    let mut results = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let mut req = ureq::get(url);
+        for (param, value) in query {
+            req.query(param, value);
+        }
+        let resp = call::<Vec<T>>(
+            req.query("page", &page.to_string())
+                .query("per_page", &per_page.to_string())
+                .set("Content-Type", "application/json")
+                .set("Authorization", &format!("Bearer {}", token)),
+            None,
+        )?;
+        results.extend(resp.result.unwrap_or_default());
+
+        match resp.result_info {
+            Some(info) if info.page < info.total_pages => page = info.page + 1,
+            _ => break,
+        }
+    }
+
+    Ok(results)
+}
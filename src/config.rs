@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Invalid;
+use crate::record::{validate_ttl, PROXIABLE_TYPES, TTL_AUTO};
+use crate::token;
+
+pub const DEFAULT_PROFILE: &str = "default";
+pub const DEFAULT_IP_URL: &str = "https://ipinfo.io/ip";
+pub const DEFAULT_IPV6_URL: &str = "https://api6.ipify.org";
+
+/// The TOML configuration file, holding named profiles:
+///
+/// ```toml
+/// default_profile = "home"
+///
+/// [profiles.home]
+/// token = "..."                     # or one of:
+/// token_file = "/etc/cf-record/token"
+/// token_command = "pass show cloudflare"
+/// zone = "example.com"
+/// ttl = 300
+/// proxied = false
+/// ip_url = "https://ipinfo.io/ip"
+/// ipv6_url = "https://api6.ipify.org"
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub token: Option<String>,
+    pub token_file: Option<PathBuf>,
+    pub token_command: Option<String>,
+    pub zone: Option<String>,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+    pub ip_url: Option<String>,
+    pub ipv6_url: Option<String>,
+}
+
+impl ConfigFile {
+    /// Read the config file at `path`, or at the default location if none
+    /// was given. Only an explicitly given file has to exist.
+    pub fn load(path: Option<&Path>) -> Result<ConfigFile> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(ConfigFile::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => {
+                return Ok(ConfigFile::default())
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read config file {}", path.display()))
+            }
+        };
+        let mut config: ConfigFile = toml::from_str(&text)
+            .map_err(|err| Invalid(format!("Invalid config file {}: {}", path.display(), err)))?;
+        if config.profiles.values().any(|p| p.token.is_some()) {
+            token::warn_if_world_readable(&path);
+        }
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// Pick the profile named on the command line, or the default one,
+    /// along with its name if there was one.
+    pub fn profile(&self, name: Option<&str>) -> Result<(Option<String>, Profile)> {
+        let explicit = name.or(self.default_profile.as_deref());
+        let name = explicit.unwrap_or(DEFAULT_PROFILE);
+        match self.profiles.get(name) {
+            Some(profile) => Ok((Some(name.to_owned()), profile.clone())),
+            None if explicit.is_none() => Ok((None, Profile::default())),
+            None => Err(Invalid(format!("No profile named {} in the config file", name)).into()),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/cf-record/config.toml`, or under `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("cf-record").join("config.toml"))
+}
+
+/// `$XDG_CACHE_HOME/cf-record`, or under `~/.cache`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(dir.join("cf-record"))
+}
+
+/// Read `name` from the cache directory, or the default if it is missing or
+/// unreadable.
+pub fn load_cache<T: DeserializeOwned + Default>(name: &str) -> T {
+    cache_dir()
+        .and_then(|dir| fs::read_to_string(dir.join(name)).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Write `name` to the cache directory. The cache is only an optimisation,
+/// so failing to write it is not an error.
+pub fn save_cache<T: Serialize + ?Sized>(name: &str, value: &T) {
+    if let Some(dir) = cache_dir() {
+        let _ = fs::create_dir_all(&dir);
+        if let Ok(text) = serde_json::to_string(value) {
+            let _ = fs::write(dir.join(name), text);
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/cf-record`, or under `~/.local/share`.
+pub fn data_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(dir.join("cf-record"))
+}
+
+/// Everything resolved from flags, environment and the config profile, in
+/// that order of precedence, falling back to built-in defaults.
+pub struct Settings {
+    /// The config file that was read, if there was one.
+    pub config_path: Option<PathBuf>,
+    /// The profile in use, if any.
+    pub profile: Option<String>,
+    pub token: String,
+    /// Where the token came from, for diagnostics.
+    pub token_source: String,
+    /// Zone name or ID, if one was given anywhere.
+    pub zone: Option<String>,
+    pub ttl: u32,
+    pub proxied: bool,
+    pub ip_url: String,
+    pub ipv6_url: String,
+    pub per_page: u32,
+    pub switches: Switches,
+}
+
+/// What a command can't run without.
+pub struct Needs {
+    pub zone: bool,
+    pub token: bool,
+}
+
+/// Command line switches that change how records are changed, passed
+/// through as given.
+#[derive(Clone, Copy)]
+pub struct Switches {
+    /// Print changes instead of making them.
+    pub dry_run: bool,
+    /// Go ahead without asking.
+    pub yes: bool,
+    /// The most records that may be deleted at once.
+    pub max_delete: usize,
+}
+
+impl Settings {
+    /// Resolve every setting before anything is sent over the network,
+    /// reporting all that are missing or invalid at once.
+    pub fn load(
+        config: Option<&Path>,
+        profile: Option<&str>,
+        zone: Option<&str>,
+        per_page: u32,
+        switches: Switches,
+        needs: Needs,
+    ) -> Result<Settings> {
+        let file = ConfigFile::load(config)?;
+        let (profile_name, profile) = file.profile(profile)?;
+        let mut problems = Vec::new();
+
+        let token = match token::load(&profile) {
+            _ if !needs.token => None,
+            Ok(Some(token)) => Some(token),
+            Ok(None) => {
+                problems.push(
+                    "No API token: set $CF_ZONE_TOKEN or $CF_ZONE_TOKEN_FILE, provide the \
+                     cf-zone-token systemd credential, or add token, token_file or \
+                     token_command to the config profile"
+                        .to_owned(),
+                );
+                None
+            }
+            Err(err) => {
+                problems.push(format!("{:#}", err));
+                None
+            }
+        };
+
+        let zone = zone
+            .map(str::to_owned)
+            .or_else(|| env::var("CF_ZONE_ID").ok())
+            .or(profile.zone);
+        if zone.is_none() && needs.zone {
+            problems.push(
+                "No zone: pass --zone, set $CF_ZONE or $CF_ZONE_ID, or add zone to the config \
+                 profile"
+                    .to_owned(),
+            );
+        }
+
+        let ttl = profile.ttl.unwrap_or(TTL_AUTO);
+        if let Err(err) = validate_ttl(ttl) {
+            problems.push(format!("Invalid ttl in the config profile: {}", err));
+        }
+        if !(5..=5_000_000).contains(&per_page) {
+            problems.push("--per-page must be between 5 and 5000000".to_owned());
+        }
+
+        if !problems.is_empty() {
+            return Err(Invalid(format!(
+                "Cannot continue with the current settings:\n  - {}",
+                problems.join("\n  - ")
+            ))
+            .into());
+        }
+        // Only commands that don't need a token go without one.
+        let (token, token_source) = match token {
+            Some(token) => (token.secret, token.source),
+            None => (String::new(), "none".to_owned()),
+        };
+        Ok(Settings {
+            config_path: file.path,
+            profile: profile_name,
+            token,
+            token_source,
+            zone,
+            ttl,
+            proxied: profile.proxied.unwrap_or(false),
+            ip_url: profile.ip_url.unwrap_or_else(|| DEFAULT_IP_URL.to_owned()),
+            ipv6_url: profile
+                .ipv6_url
+                .unwrap_or_else(|| DEFAULT_IPV6_URL.to_owned()),
+            per_page,
+            switches,
+        })
+    }
+
+    /// Whether new records of this type should be proxied by default.
+    pub fn proxied_for(&self, r#type: &str) -> bool {
+        self.proxied && PROXIABLE_TYPES.contains(&r#type)
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::Result;
+use tabwriter::TabWriter;
+
+use crate::config::Settings;
+use crate::error::{Aborted, Invalid};
+use crate::record::Entry;
+
+/// Refuse to delete more records at once than `--max-delete` allows.
+pub fn check_deletes(count: usize, settings: &Settings) -> Result<()> {
+    if count > settings.switches.max_delete {
+        return Err(Invalid(format!(
+            "Refusing to delete {} records at once, the limit is {}; raise it with --max-delete",
+            count, settings.switches.max_delete
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Show `entries` and ask whether to go ahead with `action`. Only asks when
+/// stdin is a terminal, so scripts carry on as before; `--yes` skips the
+/// question and so does `--dry-run`, as nothing is changed then.
+pub fn confirm(action: &str, entries: &[&Entry], settings: &Settings) -> Result<()> {
+    if settings.switches.yes || settings.switches.dry_run || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    let stderr = io::stderr();
+    if !entries.is_empty() {
+        let mut tw = TabWriter::new(stderr.lock());
+        writeln!(&mut tw, "  TYPE\tNAME\tCONTENT\tTTL\tID")?;
+        for entry in entries {
+            writeln!(
+                &mut tw,
+                "  {}\t{}\t{}\t{}\t{}",
+                entry.r#type,
+                entry.name,
+                entry.display_content(),
+                entry.ttl,
+                entry.id
+            )?;
+        }
+        tw.flush()?;
+    }
+    eprint!("{}? [y/N] ", action);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(Aborted("Nothing was changed".to_owned()).into()),
+    }
+}
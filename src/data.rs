@@ -0,0 +1,429 @@
+use anyhow::Result;
+use serde_json::{Map, Number, Value};
+
+use crate::error::Invalid;
+
+/// Types Cloudflare wants a structured `data` object for, rather than content.
+pub const STRUCTURED_TYPES: &[&str] = &[
+    "CAA", "CERT", "DNSKEY", "DS", "HTTPS", "LOC", "NAPTR", "SMIMEA", "SRV", "SSHFP", "SVCB",
+    "TLSA", "URI",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Float,
+    Text,
+    /// Text that is written as a quoted string in zone files.
+    Quoted,
+}
+
+struct Field {
+    name: &'static str,
+    kind: Kind,
+}
+
+const fn field(name: &'static str, kind: Kind) -> Field {
+    Field { name, kind }
+}
+
+use Kind::*;
+
+const SRV: &[Field] = &[
+    field("priority", Int),
+    field("weight", Int),
+    field("port", Int),
+    field("target", Text),
+];
+const CAA: &[Field] = &[
+    field("flags", Int),
+    field("tag", Text),
+    field("value", Quoted),
+];
+const TLSA: &[Field] = &[
+    field("usage", Int),
+    field("selector", Int),
+    field("matching_type", Int),
+    field("certificate", Text),
+];
+const SSHFP: &[Field] = &[
+    field("algorithm", Int),
+    field("type", Int),
+    field("fingerprint", Text),
+];
+const CERT: &[Field] = &[
+    field("type", Int),
+    field("key_tag", Int),
+    field("algorithm", Int),
+    field("certificate", Text),
+];
+const URI: &[Field] = &[field("weight", Int), field("target", Quoted)];
+const SVCB: &[Field] = &[
+    field("priority", Int),
+    field("target", Text),
+    field("value", Text),
+];
+const DS: &[Field] = &[
+    field("key_tag", Int),
+    field("algorithm", Int),
+    field("digest_type", Int),
+    field("digest", Text),
+];
+const DNSKEY: &[Field] = &[
+    field("flags", Int),
+    field("protocol", Int),
+    field("algorithm", Int),
+    field("public_key", Text),
+];
+const NAPTR: &[Field] = &[
+    field("order", Int),
+    field("preference", Int),
+    field("flags", Quoted),
+    field("service", Quoted),
+    field("regex", Quoted),
+    field("replacement", Text),
+];
+const LOC: &[Field] = &[
+    field("lat_degrees", Int),
+    field("lat_minutes", Int),
+    field("lat_seconds", Float),
+    field("lat_direction", Text),
+    field("long_degrees", Int),
+    field("long_minutes", Int),
+    field("long_seconds", Float),
+    field("long_direction", Text),
+    field("altitude", Float),
+    field("size", Float),
+    field("precision_horz", Float),
+    field("precision_vert", Float),
+];
+
+fn fields(r#type: &str) -> &'static [Field] {
+    match r#type {
+        "SRV" => SRV,
+        "CAA" => CAA,
+        "TLSA" | "SMIMEA" => TLSA,
+        "SSHFP" => SSHFP,
+        "CERT" => CERT,
+        "URI" => URI,
+        "HTTPS" | "SVCB" => SVCB,
+        "DS" => DS,
+        "DNSKEY" => DNSKEY,
+        "NAPTR" => NAPTR,
+        "LOC" => LOC,
+        _ => &[],
+    }
+}
+
+pub fn is_structured(r#type: &str) -> bool {
+    STRUCTURED_TYPES.contains(&r#type)
+}
+
+/// Whether records of this type keep their priority inside `data`.
+pub fn has_priority(r#type: &str) -> bool {
+    fields(r#type).iter().any(|f| f.name == "priority")
+}
+
+/// Set a single field of `data`, checking it belongs to the type.
+pub fn set_field(
+    data: &mut Map<String, Value>,
+    r#type: &str,
+    key: &str,
+    value: &str,
+) -> Result<()> {
+    let field = match fields(r#type).iter().find(|f| f.name == key) {
+        Some(field) => field,
+        None => {
+            let names: Vec<_> = fields(r#type).iter().map(|f| f.name).collect();
+            return Err(Invalid(format!(
+                "{} records have no {} field (expected one of: {})",
+                r#type,
+                key,
+                names.join(", ")
+            ))
+            .into());
+        }
+    };
+    data.insert(key.to_owned(), parse_value(r#type, field, value)?);
+    Ok(())
+}
+
+fn parse_value(r#type: &str, field: &Field, value: &str) -> Result<Value> {
+    let invalid = || {
+        Invalid(format!(
+            "{} is not a valid {} {}",
+            value, r#type, field.name
+        ))
+    };
+    Ok(match field.kind {
+        Int => Value::Number(value.parse::<u64>().map_err(|_| invalid())?.into()),
+        Float => Value::Number(
+            value
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .ok_or_else(invalid)?,
+        ),
+        Text | Quoted => Value::String(value.to_owned()),
+    })
+}
+
+/// Check every field the type needs is present.
+pub fn check(r#type: &str, data: &Map<String, Value>) -> Result<()> {
+    let missing: Vec<_> = fields(r#type)
+        .iter()
+        .filter(|f| !data.contains_key(f.name))
+        .map(|f| f.name)
+        .collect();
+    if !missing.is_empty() {
+        return Err(Invalid(format!(
+            "{} record is missing: {}",
+            r#type,
+            missing.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Parse record data in zone file presentation format, e.g.
+/// `10 5 5060 sip.example.com` for SRV or `0 issue "letsencrypt.org"` for CAA.
+pub fn parse(r#type: &str, rdata: &str) -> Result<Map<String, Value>> {
+    let tokens = tokenize(rdata)?;
+    if r#type == "LOC" {
+        return parse_loc(&tokens);
+    }
+
+    let fields = fields(r#type);
+    if tokens.len() < fields.len() {
+        return Err(Invalid(format!(
+            "{} record data needs {} fields, got \"{}\"",
+            r#type,
+            fields.len(),
+            rdata
+        ))
+        .into());
+    }
+
+    let mut data = Map::new();
+    for (i, field) in fields.iter().enumerate() {
+        // The last field soaks up the rest, as certificates and SVCB
+        // parameters may contain spaces.
+        let value = if i == fields.len() - 1 {
+            tokens[i..].join(" ")
+        } else {
+            tokens[i].clone()
+        };
+        data.insert(field.name.to_owned(), parse_value(r#type, field, &value)?);
+    }
+    Ok(data)
+}
+
+fn parse_loc(tokens: &[String]) -> Result<Map<String, Value>> {
+    let invalid = || Invalid(format!("Invalid LOC record data: {}", tokens.join(" ")));
+    let mut data = Map::new();
+    let mut rest = tokens.iter().map(|t| t.as_str());
+
+    for (prefix, directions) in &[("lat", ["N", "S"]), ("long", ["E", "W"])] {
+        let mut parts = Vec::new();
+        loop {
+            match rest.next() {
+                Some(dir) if directions.contains(&dir) => {
+                    data.insert(format!("{}_direction", prefix), Value::from(dir));
+                    break;
+                }
+                Some(part) if parts.len() < 3 => parts.push(part),
+                _ => return Err(invalid().into()),
+            }
+        }
+        let units = ["degrees", "minutes", "seconds"];
+        for (i, unit) in units.iter().enumerate() {
+            let name = format!("{}_{}", prefix, unit);
+            let field = LOC.iter().find(|f| f.name == name).unwrap();
+            let value = parts.get(i).copied().unwrap_or("0");
+            data.insert(name, parse_value("LOC", field, value)?);
+        }
+    }
+
+    let sizes = [
+        ("altitude", None),
+        ("size", Some("1")),
+        ("precision_horz", Some("10000")),
+        ("precision_vert", Some("10")),
+    ];
+    for (name, default) in &sizes {
+        let value = match (rest.next(), default) {
+            (Some(value), _) => value.trim_end_matches('m'),
+            (None, Some(default)) => default,
+            (None, None) => return Err(invalid().into()),
+        };
+        let field = LOC.iter().find(|f| &f.name == name).unwrap();
+        data.insert((*name).to_owned(), parse_value("LOC", field, value)?);
+    }
+    Ok(data)
+}
+
+/// Render record data in zone file presentation format.
+pub fn render(r#type: &str, data: &Value) -> String {
+    let get = |name: &str| data.get(name).map(render_value).unwrap_or_default();
+    if r#type == "LOC" {
+        return format!(
+            "{} {} {} {} {} {} {} {} {}m {}m {}m {}m",
+            get("lat_degrees"),
+            get("lat_minutes"),
+            get("lat_seconds"),
+            get("lat_direction"),
+            get("long_degrees"),
+            get("long_minutes"),
+            get("long_seconds"),
+            get("long_direction"),
+            get("altitude"),
+            get("size"),
+            get("precision_horz"),
+            get("precision_vert"),
+        );
+    }
+
+    fields(r#type)
+        .iter()
+        .map(|f| match f.kind {
+            Quoted => quote(&get(f.name)),
+            _ => get(f.name),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        // Whole numbers go without the `.0`, however they were parsed.
+        Value::Number(n) if n.is_f64() => n.as_f64().unwrap_or_default().to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Wrap `s` in double quotes, escaping what needs escaping.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split presentation format data on whitespace, keeping quoted strings
+/// together and unescaping them.
+pub fn tokenize(s: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek().is_some() => token.extend(chars.next()),
+                    Some(c) if c != '\\' => token.push(c),
+                    _ => {
+                        return Err(Invalid(format!("Unterminated quoted string in: {}", s)).into())
+                    }
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn tokenize_keeps_quoted_strings_together() {
+        assert_eq!(
+            tokenize(r#"0  issue "lets encrypt.org"  "#).unwrap(),
+            ["0", "issue", "lets encrypt.org"]
+        );
+        assert_eq!(
+            tokenize(r#""say \"hi\"" "back\\slash" """#).unwrap(),
+            [r#"say "hi""#, r"back\slash", ""]
+        );
+    }
+
+    #[test]
+    fn tokenize_rejects_unterminated_quotes() {
+        assert!(tokenize(r#"0 issue "letsencrypt.org"#).is_err());
+        assert!(tokenize(r#"0 issue "letsencrypt.org\"#).is_err());
+    }
+
+    #[test]
+    fn last_field_soaks_up_the_rest() {
+        let data = parse("SVCB", "1 . alpn=h2 port=8443").unwrap();
+        assert_eq!(
+            Value::Object(data),
+            json!({"priority": 1, "target": ".", "value": "alpn=h2 port=8443"})
+        );
+        assert!(parse("SRV", "10 5 5060").is_err());
+        assert!(parse("SRV", "ten 5 5060 sip.example.com").is_err());
+    }
+
+    #[test]
+    fn loc_fills_in_defaults() {
+        let data = parse("LOC", "52 N 4 22 E 0m").unwrap();
+        assert_eq!(
+            Value::Object(data),
+            json!({
+                "lat_degrees": 52, "lat_minutes": 0, "lat_seconds": 0.0, "lat_direction": "N",
+                "long_degrees": 4, "long_minutes": 22, "long_seconds": 0.0,
+                "long_direction": "E", "altitude": 0.0, "size": 1.0,
+                "precision_horz": 10000.0, "precision_vert": 10.0
+            })
+        );
+    }
+
+    #[test]
+    fn loc_reads_all_directions() {
+        let data = parse("LOC", "33 51 35.9 S 151 12 40 W 10m 2m 100m 5m").unwrap();
+        assert_eq!(data["lat_direction"], "S");
+        assert_eq!(data["lat_seconds"], 35.9);
+        assert_eq!(data["long_direction"], "W");
+        assert_eq!(data["precision_vert"], 5.0);
+        // Directions are required, and only N/S then E/W will do.
+        assert!(parse("LOC", "52 4 22 E 0m").is_err());
+        assert!(parse("LOC", "52 E 4 N 0m").is_err());
+        assert!(parse("LOC", "52 N 4 E").is_err());
+    }
+
+    #[test]
+    fn render_round_trips() {
+        for (r#type, rdata) in &[
+            ("SRV", "10 5 5060 sip.example.com."),
+            (
+                "CAA",
+                r#"0 issue "letsencrypt.org; validationmethods=\"dns-01\"""#,
+            ),
+            ("NAPTR", r#"100 10 "S" "SIP+D2U" "" _sip._udp.example.com."#),
+            ("TLSA", "3 1 1 0123456789abcdef"),
+            ("LOC", "52 22 23.5 N 4 53 32.1 E -2m 1m 10000m 10m"),
+        ] {
+            let data = Value::Object(parse(r#type, rdata).unwrap());
+            assert_eq!(render(r#type, &data), *rdata, "{}", r#type);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+use crate::api::{ApiError, NetworkError};
+
+// Exit codes, as documented in the README.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_NOT_FOUND: i32 = 2;
+pub const EXIT_INVALID: i32 = 3;
+pub const EXIT_API: i32 = 4;
+pub const EXIT_AUTH: i32 = 5;
+pub const EXIT_NETWORK: i32 = 6;
+pub const EXIT_ABORTED: i32 = 7;
+
+/// Cloudflare error codes that mean the token itself was rejected.
+const AUTH_ERROR_CODES: &[u32] = &[9103, 9106, 9109, 10000];
+
+/// The record (or other object) the user asked for does not exist.
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// The user's input was rejected before anything was sent to Cloudflare.
+#[derive(Debug)]
+pub struct Invalid(pub String);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Invalid {}
+
+/// The token is valid but cannot be used for what was asked.
+#[derive(Debug)]
+pub struct Unauthorized(pub String);
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// The user declined to go ahead when asked.
+#[derive(Debug)]
+pub struct Aborted(pub String);
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Aborted {}
+
+impl ApiError {
+    pub fn is_auth(&self) -> bool {
+        self.status == 401
+            || self.status == 403
+            || self
+                .errors
+                .iter()
+                .any(|err| AUTH_ERROR_CODES.contains(&err.code))
+    }
+}
+
+/// Map an error to the exit code of the first typed error in its chain.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if cause.is::<NotFound>() {
+            return EXIT_NOT_FOUND;
+        }
+        if cause.is::<Invalid>() {
+            return EXIT_INVALID;
+        }
+        if cause.is::<Unauthorized>() {
+            return EXIT_AUTH;
+        }
+        if let Some(api) = cause.downcast_ref::<ApiError>() {
+            return if api.is_auth() { EXIT_AUTH } else { EXIT_API };
+        }
+        if cause.is::<NetworkError>() {
+            return EXIT_NETWORK;
+        }
+        if cause.is::<Aborted>() {
+            return EXIT_ABORTED;
+        }
+    }
+    EXIT_FAILURE
+}
@@ -0,0 +1,193 @@
+use std::net::IpAddr;
+
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+
+use crate::error::Invalid;
+use crate::record::{self, Entry};
+use crate::zone::Zone;
+
+/// Which records `show` lists. Every condition given must hold.
+pub struct Filter {
+    /// Upper case types, any of which may match; empty for every type.
+    pub types: Vec<String>,
+    pub name: Option<Regex>,
+    pub content: Option<ContentMatch>,
+    pub proxied: Option<bool>,
+    /// Lower case text the comment must contain.
+    pub comment: Option<String>,
+    /// Tags the record must all have.
+    pub tags: Vec<String>,
+}
+
+pub enum ContentMatch {
+    /// Lower case text the content must contain.
+    Text(String),
+    /// A network that A and AAAA record addresses must be inside.
+    Network(IpAddr, u8),
+}
+
+impl Filter {
+    /// Parse `types` as a comma separated list of record types, with `all`
+    /// standing for every type.
+    pub fn parse_types(types: &str) -> Result<Vec<String>> {
+        if types.eq_ignore_ascii_case("all") {
+            return Ok(Vec::new());
+        }
+        types
+            .split(',')
+            .map(|t| {
+                let t = t.trim().to_uppercase();
+                record::validate_type(&t)?;
+                Ok(t)
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, entry: &Entry, zone: &Zone) -> bool {
+        (self.types.is_empty() || self.types.contains(&entry.r#type))
+            && self.name.as_ref().is_none_or(|re| {
+                re.is_match(&entry.name) || re.is_match(&zone.relative(&entry.name))
+            })
+            && self.content.as_ref().is_none_or(|c| c.matches(entry))
+            && self.proxied.is_none_or(|p| entry.proxied == p)
+            && self.comment.as_ref().is_none_or(|c| {
+                entry
+                    .comment
+                    .as_ref()
+                    .is_some_and(|comment| comment.to_lowercase().contains(c))
+            })
+            && self
+                .tags
+                .iter()
+                .all(|tag| entry.tags.iter().any(|t| tag_matches(t, tag)))
+    }
+}
+
+/// A tag given as `name` matches any `name:value` tag as well as itself.
+fn tag_matches(tag: &str, wanted: &str) -> bool {
+    tag.eq_ignore_ascii_case(wanted)
+        || (!wanted.contains(':')
+            && tag
+                .split(':')
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case(wanted)))
+}
+
+/// Compile a name pattern: `/regex/`, or else a glob where `*` matches any
+/// run of characters and `?` a single one. Either way case is ignored.
+pub fn name_pattern(pattern: &str) -> Result<Regex> {
+    let regex = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+        Some(regex) => regex.to_owned(),
+        None => {
+            let mut regex = String::from("^");
+            for c in pattern.trim_end_matches('.').chars() {
+                match c {
+                    '*' => regex.push_str(".*"),
+                    '?' => regex.push('.'),
+                    c => regex.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            regex.push('$');
+            regex
+        }
+    };
+    RegexBuilder::new(&regex)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| Invalid(format!("Invalid name pattern {}: {}", pattern, err)).into())
+}
+
+impl ContentMatch {
+    /// A CIDR network such as `10.0.0.0/8` matches addresses inside it,
+    /// anything else is a case insensitive substring.
+    pub fn parse(content: &str) -> Result<ContentMatch> {
+        let (addr, len) = match content.split_once('/') {
+            Some(parts) => parts,
+            None => return Ok(ContentMatch::Text(content.to_lowercase())),
+        };
+        let addr = match addr.parse::<IpAddr>() {
+            Ok(addr) => addr,
+            Err(_) => return Ok(ContentMatch::Text(content.to_lowercase())),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        match len.parse::<u8>() {
+            Ok(len) if len <= max => Ok(ContentMatch::Network(addr, len)),
+            _ => Err(Invalid(format!("Invalid network prefix length in {}", content)).into()),
+        }
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        match self {
+            ContentMatch::Text(text) => entry.display_content().to_lowercase().contains(text),
+            ContentMatch::Network(network, len) => entry
+                .content
+                .parse::<IpAddr>()
+                .is_ok_and(|addr| in_network(addr, *network, *len)),
+        }
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, len: u8) -> bool {
+    let (addr, network, bits) = match (addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+        _ => return false,
+    };
+    if len == 0 {
+        return true;
+    }
+    let shift = bits - u32::from(len);
+    addr >> shift == network >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn networks() {
+        assert!(in_network(ip("10.1.2.3"), ip("10.0.0.0"), 8));
+        assert!(!in_network(ip("11.1.2.3"), ip("10.0.0.0"), 8));
+        assert!(in_network(ip("192.0.2.255"), ip("192.0.2.0"), 24));
+        assert!(!in_network(ip("192.0.3.0"), ip("192.0.2.0"), 24));
+        assert!(in_network(ip("203.0.113.10"), ip("203.0.113.10"), 32));
+        assert!(!in_network(ip("203.0.113.11"), ip("203.0.113.10"), 32));
+        assert!(in_network(ip("198.51.100.1"), ip("0.0.0.0"), 0));
+        assert!(in_network(ip("2001:db8::1"), ip("2001:db8::"), 32));
+        assert!(!in_network(ip("2001:db9::1"), ip("2001:db8::"), 32));
+        assert!(in_network(ip("::1"), ip("::"), 0));
+        assert!(!in_network(ip("10.0.0.1"), ip("::"), 0));
+    }
+
+    #[test]
+    fn content_patterns() {
+        assert!(matches!(
+            ContentMatch::parse("10.0.0.0/8").unwrap(),
+            ContentMatch::Network(_, 8)
+        ));
+        assert!(matches!(
+            ContentMatch::parse("v=DKIM1/x").unwrap(),
+            ContentMatch::Text(_)
+        ));
+        assert!(ContentMatch::parse("10.0.0.0/33").is_err());
+        assert!(ContentMatch::parse("2001:db8::/129").is_err());
+        assert!(ContentMatch::parse("2001:db8::/128").is_ok());
+    }
+
+    #[test]
+    fn name_patterns() {
+        let glob = name_pattern("*.dev").unwrap();
+        assert!(glob.is_match("api.DEV"));
+        assert!(!glob.is_match("api.dev.example.com"));
+        assert!(name_pattern("w?w.").unwrap().is_match("www"));
+        assert!(name_pattern("/^_dmarc/")
+            .unwrap()
+            .is_match("_dmarc.example.com"));
+        assert!(name_pattern("/(/").is_err());
+    }
+}
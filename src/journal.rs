@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error::Invalid;
+use crate::record::{Entry, RecordBody, RecordPatch};
+use crate::time;
+use crate::zone::Zone;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+/// One change made from this machine, as a line of the journal.
+#[derive(Serialize, Deserialize)]
+pub struct Operation {
+    pub seq: u64,
+    pub time: String,
+    pub user: String,
+    pub zone_id: String,
+    pub zone: String,
+    pub action: Action,
+    pub before: Option<Entry>,
+    pub after: Option<Entry>,
+    /// The operation this one reverted, if it was made by `undo`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<u64>,
+}
+
+impl Operation {
+    /// The record as it was before the change, or after it for a create.
+    pub fn record(&self) -> Option<&Entry> {
+        self.before.as_ref().or(self.after.as_ref())
+    }
+}
+
+/// How much of the journal's end is read at a time to find the last entry.
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+/// `$XDG_DATA_HOME/cf-record/journal.jsonl`, or under `~/.local/share`.
+fn path() -> Option<PathBuf> {
+    config::data_dir().map(|dir| dir.join("journal.jsonl"))
+}
+
+/// Every operation in the journal, oldest first. Lines that can't be read
+/// are skipped with a warning.
+pub fn load() -> Result<Vec<Operation>> {
+    let path = match path() {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    // A run cut short may leave a partial line, which mustn't hide the rest.
+    let ops = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(op) => Some(op),
+            Err(err) => {
+                eprintln!(
+                    "Warning: skipping {}:{}: invalid journal entry: {}",
+                    path.display(),
+                    i + 1,
+                    err
+                );
+                None
+            }
+        })
+        .collect();
+    Ok(ops)
+}
+
+/// Append an operation to the journal. The change has already been made by
+/// then, so failing to record it is only a warning.
+pub fn append(
+    zone: &Zone,
+    action: Action,
+    before: Option<&Entry>,
+    after: Option<&Entry>,
+    undoes: Option<u64>,
+) {
+    if let Err(err) = try_append(zone, action, before, after, undoes) {
+        eprintln!("Warning: failed to write the journal: {:#}", err);
+    }
+}
+
+fn try_append(
+    zone: &Zone,
+    action: Action,
+    before: Option<&Entry>,
+    after: Option<&Entry>,
+    undoes: Option<u64>,
+) -> Result<()> {
+    let path = path().context("Cannot find the journal: $HOME is not set")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(&path)?;
+    // Held until the file is closed, so that concurrent runs take turns
+    // numbering their operations.
+    file.lock()?;
+    let seq = last_seq(&mut file)?.map_or(1, |seq| seq + 1);
+    let op = Operation {
+        seq,
+        time: time::rfc3339(time::now()),
+        user: user(),
+        zone_id: zone.id.clone(),
+        zone: zone.name.clone(),
+        action,
+        before: before.cloned(),
+        after: after.cloned(),
+        undoes,
+    };
+    writeln!(file, "{}", serde_json::to_string(&op)?)?;
+    Ok(())
+}
+
+/// The number of the last operation in the journal, read from its end so
+/// appending stays cheap however long the journal gets. Lines that can't
+/// be read are passed over.
+fn last_seq(file: &mut File) -> Result<Option<u64>> {
+    #[derive(Deserialize)]
+    struct Seq {
+        seq: u64,
+    }
+
+    let mut start = file.metadata()?.len();
+    let mut tail = Vec::new();
+    while start > 0 {
+        let chunk = TAIL_CHUNK.min(start);
+        start -= chunk;
+        let mut buf = vec![0; chunk as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buf)?;
+        buf.extend_from_slice(&tail);
+        tail = buf;
+
+        // The first line may have been cut short, unless it starts the file.
+        let lines = match tail.iter().position(|&b| b == b'\n') {
+            _ if start == 0 => &tail[..],
+            Some(at) => &tail[at + 1..],
+            None => continue,
+        };
+        if let Some(seq) = lines
+            .split(|&b| b == b'\n')
+            .rev()
+            .find_map(|line| serde_json::from_slice::<Seq>(line).ok())
+        {
+            return Ok(Some(seq.seq));
+        }
+    }
+    Ok(None)
+}
+
+/// The last `n` operations that can still be undone, newest first: those
+/// not made by `undo` and not undone already.
+pub fn undoable(ops: &[Operation], n: usize) -> Vec<&Operation> {
+    let undone: Vec<u64> = ops.iter().filter_map(|op| op.undoes).collect();
+    ops.iter()
+        .rev()
+        .filter(|op| op.undoes.is_none() && !undone.contains(&op.seq))
+        .take(n)
+        .collect()
+}
+
+/// An operation to undo, with the current state of its record.
+pub type Step<'a> = (&'a Operation, Option<Entry>);
+
+/// Pair each operation to undo, newest first, with its record as it is
+/// now, or as undoing the newer operations will leave it, and list those
+/// whose record has changed since they were made. Records not yet seen are
+/// looked up with `fetch`, by the ID they were journaled under.
+pub fn plan_undo(
+    ops: Vec<&Operation>,
+    mut fetch: impl FnMut(&Operation, &str) -> Result<Option<Entry>>,
+) -> Result<(Vec<Step<'_>>, Vec<String>)> {
+    let mut state: HashMap<String, Option<Entry>> = HashMap::new();
+    let mut steps = Vec::new();
+    let mut conflicts = Vec::new();
+    for op in ops {
+        let record = op
+            .record()
+            .ok_or_else(|| Invalid(format!("Journal entry #{} is incomplete", op.seq)))?;
+        let current = match state.get(&record.id) {
+            Some(current) => current.clone(),
+            None => fetch(op, &record.id)?,
+        };
+        if !same_state(current.as_ref(), op.after.as_ref()) {
+            let then = match &op.after {
+                Some(entry) => entry.display_content(),
+                None => "deleted".to_owned(),
+            };
+            let now = match &current {
+                Some(entry) => format!("now {}", entry.display_content()),
+                None => "now deleted".to_owned(),
+            };
+            conflicts.push(format!(
+                "#{} {} {}: was {} after it, {}",
+                op.seq, record.name, record.r#type, then, now
+            ));
+        }
+        state.insert(record.id.clone(), op.before.clone());
+        steps.push((op, current));
+    }
+    Ok((steps, conflicts))
+}
+
+/// Whether a record is as the journal says an operation left it: gone, or
+/// with the same name, type, content and settings.
+fn same_state(current: Option<&Entry>, journaled: Option<&Entry>) -> bool {
+    match (current, journaled) {
+        (None, None) => true,
+        (Some(current), Some(journaled)) => {
+            current.name.eq_ignore_ascii_case(&journaled.name)
+                && current.r#type == journaled.r#type
+                && RecordPatch::between(current, &RecordBody::from(journaled)).is_empty()
+        }
+        _ => false,
+    }
+}
+
+fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn last_seq_of(name: &str, text: &str) -> Option<u64> {
+        let dir = TestDir::new(&format!("journal-{}", name));
+        let path = dir.join("journal.jsonl");
+        fs::write(&path, text).unwrap();
+        last_seq(&mut File::open(&path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn last_seq_reads_the_end() {
+        assert_eq!(last_seq_of("empty", ""), None);
+        assert_eq!(last_seq_of("one", "{\"seq\":1}\n"), Some(1));
+        assert_eq!(last_seq_of("two", "{\"seq\":1}\n{\"seq\":2}\n"), Some(2));
+    }
+
+    #[test]
+    fn last_seq_passes_over_bad_lines() {
+        let text = format!(
+            "{{\"seq\":7}}\n{{\"seq\":8, \"pad\":\"{}\"}}\nnot json\n{{\"seq\":",
+            "x".repeat(200_000)
+        );
+        assert_eq!(last_seq_of("bad", &text), Some(8));
+        assert_eq!(last_seq_of("garbage", "not json\n"), None);
+    }
+
+    fn op(seq: u64, action: Action, before: Option<Entry>, after: Option<Entry>) -> Operation {
+        Operation {
+            seq,
+            time: String::new(),
+            user: String::new(),
+            zone_id: String::new(),
+            zone: String::new(),
+            action,
+            before,
+            after,
+            undoes: None,
+        }
+    }
+
+    fn a(content: &str) -> Option<Entry> {
+        Some(
+            serde_json::from_value(json!({
+                "id": "r1", "name": "www.example.com", "type": "A", "content": content
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn undone_operations_are_skipped() {
+        let mut ops: Vec<_> = (1..=5)
+            .map(|seq| op(seq, Action::Create, None, a("203.0.113.10")))
+            .collect();
+        ops[4].undoes = Some(4);
+        let seqs: Vec<_> = undoable(&ops, 2).iter().map(|op| op.seq).collect();
+        assert_eq!(seqs, vec![3, 2]);
+    }
+
+    #[test]
+    fn undo_follows_the_record_through_newer_operations() {
+        let ops = vec![
+            op(1, Action::Create, None, a("203.0.113.10")),
+            op(2, Action::Update, a("203.0.113.10"), a("203.0.113.11")),
+            op(3, Action::Delete, a("203.0.113.11"), None),
+        ];
+        let mut fetched = 0;
+        let (steps, conflicts) = plan_undo(undoable(&ops, 3), |_, _| {
+            fetched += 1;
+            Ok(None)
+        })
+        .unwrap();
+        assert_eq!(fetched, 1);
+        assert!(conflicts.is_empty());
+        let states: Vec<_> = steps
+            .iter()
+            .map(|(op, current)| (op.seq, current.as_ref().map(Entry::display_content)))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (3, None),
+                (2, Some("203.0.113.11".to_owned())),
+                (1, Some("203.0.113.10".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_made_since_are_conflicts() {
+        let ops = vec![
+            op(1, Action::Create, None, a("203.0.113.10")),
+            op(2, Action::Update, a("203.0.113.10"), a("203.0.113.11")),
+        ];
+        let (_, conflicts) = plan_undo(undoable(&ops, 1), |_, _| Ok(a("203.0.113.12"))).unwrap();
+        assert_eq!(
+            conflicts,
+            vec!["#2 www.example.com A: was 203.0.113.11 after it, now 203.0.113.12"]
+        );
+
+        let (_, conflicts) = plan_undo(undoable(&ops[..1], 1), |_, _| Ok(None)).unwrap();
+        assert_eq!(
+            conflicts,
+            vec!["#1 www.example.com A: was 203.0.113.10 after it, now deleted"]
+        );
+    }
+}
@@ -1,35 +1,97 @@
-use std::env;
+use std::collections::HashMap;
 use std::fmt::Write as fmtWrite;
+use std::fs;
 use std::io::Write;
+use std::path::PathBuf;
+use std::process;
 
-use anyhow::Result;
-use clap::Clap;
-use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+use clap::{Clap, ErrorKind};
 use tabwriter::TabWriter;
 
-lazy_static! {
-    static ref ZONE: String = env::var("CF_ZONE_ID").expect("Define Zone ID in $CF_ZONE_ID");
-    static ref TOKEN: String =
-        env::var("CF_ZONE_TOKEN").expect("Define Zone Token in $CF_ZONE_TOKEN");
-    static ref ENDPOINT: String = format!(
-        "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-        *ZONE
-    );
-}
+mod api;
+mod config;
+mod confirm;
+mod data;
+mod error;
+mod filter;
+mod journal;
+mod output;
+mod plan;
+mod record;
+mod snapshot;
+#[cfg(test)]
+mod testdir;
+mod time;
+mod token;
+mod zone;
+mod zonefile;
 
-fn record_endpoint(id: &str) -> String {
-    format!("{}/{}", *ENDPOINT, id)
-}
+use config::{Needs, Settings, Switches};
+use error::{Invalid, NotFound};
+use filter::{ContentMatch, Filter};
+use journal::Action;
+use output::{Column, Format, Row, SortKey, Table};
+use plan::{Change, Desired};
+use record::{Entry, RecordBody, RecordPatch, Selector, TTL_AUTO};
+use zone::Zone;
 
 #[derive(Clap)]
 #[clap(version = env!("CARGO_PKG_VERSION"), author = "Imran Khan")]
 struct Config {
+    #[clap(
+        long = "config",
+        global = true,
+        about = "Config file to read instead of ~/.config/cf-record/config.toml"
+    )]
+    config: Option<PathBuf>,
+    #[clap(
+        long = "profile",
+        env = "CF_PROFILE",
+        global = true,
+        about = "Profile of the config file to use"
+    )]
+    profile: Option<String>,
+    #[clap(
+        long = "zone",
+        env = "CF_ZONE",
+        global = true,
+        about = "Domain name (or ID) of the zone to manage"
+    )]
+    zone: Option<String>,
+    #[clap(
+        long = "per-page",
+        default_value = "100",
+        global = true,
+        about = "Number of records to request per page when listing"
+    )]
+    per_page: u32,
+    #[clap(
+        long = "dry-run",
+        global = true,
+        about = "Print the requests that would change records, without sending them"
+    )]
+    dry_run: bool,
+    #[clap(
+        short = "y",
+        long = "yes",
+        global = true,
+        about = "Don't ask before deleting or overwriting records"
+    )]
+    yes: bool,
+    #[clap(
+        long = "max-delete",
+        default_value = "10",
+        global = true,
+        about = "Refuse to delete more than this many records at once"
+    )]
+    max_delete: usize,
     #[clap(subcommand)]
     subcmd: Subcommand,
 }
 
 #[derive(Clap)]
+#[allow(clippy::large_enum_variant)]
 enum Subcommand {
     #[clap(about = "Delete zone record")]
     Del(DelOpts),
@@ -37,16 +99,294 @@ enum Subcommand {
     Set(SetOpts),
     #[clap(about = "Show all zone records")]
     Show(ShowOpts),
+    #[clap(about = "Export the zone as a BIND zone file")]
+    Export(ExportOpts),
+    #[clap(about = "Create the records of a BIND zone file")]
+    Import(ImportOpts),
+    #[clap(about = "Show the changes that would bring the zone in line with a desired-state file")]
+    Plan(PlanOpts),
+    #[clap(about = "Bring the zone in line with a desired-state file")]
+    Apply(PlanOpts),
+    #[clap(about = "Save a snapshot of every record of the zone")]
+    Backup(BackupOpts),
+    #[clap(about = "Bring the zone back to a snapshot")]
+    Restore(RestoreOpts),
+    #[clap(about = "List the changes made from this machine")]
+    History(HistoryOpts),
+    #[clap(about = "Revert the last changes made from this machine")]
+    Undo(UndoOpts),
+    #[clap(about = "Manage the zones the token can access")]
+    Zones(ZonesOpts),
+    #[clap(about = "Inspect the configuration")]
+    Config(ConfigOpts),
+    #[clap(about = "Inspect the API token")]
+    Token(TokenOpts),
+}
+
+#[derive(Clap)]
+struct ExportOpts {
+    #[clap(
+        short = "o",
+        long = "output",
+        about = "Write the zone file here instead of to stdout"
+    )]
+    output: Option<PathBuf>,
+    #[clap(
+        long = "cloudflare",
+        about = "Use Cloudflare's own export rather than rendering the records locally"
+    )]
+    cloudflare: bool,
+}
+
+#[derive(Clap)]
+struct ImportOpts {
+    #[clap(about = "Zone file to import")]
+    file: PathBuf,
+    #[clap(
+        long = "proxied",
+        conflicts_with = "no-proxied",
+        about = "Proxy the A, AAAA and CNAME records the file doesn't say otherwise about"
+    )]
+    proxied: bool,
+    #[clap(
+        long = "no-proxied",
+        about = "Make records DNS only unless the file says otherwise"
+    )]
+    no_proxied: bool,
+}
+
+#[derive(Clap)]
+struct PlanOpts {
+    #[clap(about = "YAML or TOML file listing the records the zone should have")]
+    file: PathBuf,
+    #[clap(
+        long = "prune",
+        about = "Also delete records whose name and type the file doesn't mention"
+    )]
+    prune: bool,
+}
+
+#[derive(Clap)]
+struct BackupOpts {
+    #[clap(
+        long = "dir",
+        about = "Keep snapshots here instead of ~/.local/share/cf-record/backups"
+    )]
+    dir: Option<PathBuf>,
+    #[clap(
+        long = "keep",
+        validator = at_least_one,
+        about = "Delete all but this many of the zone's newest snapshots"
+    )]
+    keep: Option<usize>,
+    #[clap(
+        long = "list",
+        about = "List the zone's snapshots instead of taking one"
+    )]
+    list: bool,
+}
+
+fn at_least_one(value: &str) -> std::result::Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(()),
+        _ => Err("must be a whole number of at least 1".to_owned()),
+    }
+}
+
+#[derive(Clap)]
+struct RestoreOpts {
+    #[clap(about = "Snapshot file, its name among the zone's snapshots, or \"latest\"")]
+    snapshot: String,
+    #[clap(
+        long = "dir",
+        about = "Look for snapshots here instead of ~/.local/share/cf-record/backups"
+    )]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Clap)]
+struct HistoryOpts {
+    #[clap(
+        short = "n",
+        long = "limit",
+        default_value = "20",
+        about = "Show this many of the latest operations"
+    )]
+    limit: usize,
+}
+
+#[derive(Clap)]
+struct UndoOpts {
+    #[clap(default_value = "1", about = "Number of operations to undo")]
+    count: usize,
+    #[clap(
+        long = "force",
+        about = "Undo even if records have changed since, skipping those that are gone"
+    )]
+    force: bool,
+}
+
+#[derive(Clap)]
+struct TokenOpts {
+    #[clap(subcommand)]
+    subcmd: TokenSubcommand,
+}
+
+#[derive(Clap)]
+enum TokenSubcommand {
+    #[clap(about = "Verify the token and list the permissions it grants")]
+    Verify,
+}
+
+#[derive(Clap)]
+struct ConfigOpts {
+    #[clap(subcommand)]
+    subcmd: ConfigSubcommand,
+}
+
+#[derive(Clap)]
+enum ConfigSubcommand {
+    #[clap(about = "Show the settings in effect and verify the token")]
+    Check,
+}
+
+#[derive(Clap)]
+struct ZonesOpts {
+    #[clap(subcommand)]
+    subcmd: ZonesSubcommand,
+}
+
+#[derive(Clap)]
+enum ZonesSubcommand {
+    #[clap(about = "List zones with their status, plan and name servers")]
+    List,
 }
 
 #[derive(Clap)]
 struct ShowOpts {
     #[clap(
         short = "f",
+        long = "type",
         default_value = "all",
-        about = "Filter records by DNS type (e.g. A, CNAME etc.)"
+        about = "Only show records of these comma separated DNS types (e.g. A,AAAA)"
+    )]
+    types: String,
+    #[clap(
+        long = "name",
+        about = "Only show records whose name matches this glob (e.g. '*.dev'), or /regex/"
+    )]
+    name: Option<String>,
+    #[clap(
+        long = "content",
+        about = "Only show records whose content contains this text, or whose address is inside this network (e.g. 10.0.0.0/8)"
+    )]
+    content: Option<String>,
+    #[clap(
+        long = "proxied",
+        conflicts_with = "dns-only",
+        about = "Only show proxied records"
+    )]
+    proxied: bool,
+    #[clap(long = "dns-only", about = "Only show DNS only records")]
+    dns_only: bool,
+    #[clap(
+        long = "comment",
+        about = "Only show records whose comment contains this text"
+    )]
+    comment: Option<String>,
+    #[clap(
+        long = "tag",
+        number_of_values = 1,
+        about = "Only show records with this tag, as name or name:value (repeatable)"
+    )]
+    tags: Vec<String>,
+    #[clap(
+        short = "r",
+        long = "relative",
+        about = "Show names relative to the zone, with @ for the apex"
+    )]
+    relative: bool,
+    #[clap(
+        short = "a",
+        long = "all-zones",
+        about = "Show records of every zone the token can access"
+    )]
+    all_zones: bool,
+    #[clap(
+        short = "o",
+        long = "output",
+        default_value = "table",
+        possible_values = output::FORMATS,
+        about = "Output format"
     )]
-    filter: String,
+    output: Format,
+    #[clap(
+        long = "columns",
+        about = "Comma separated columns of the table: zone, id, type, name, content, ttl, proxied, priority, comment, tags, created, modified"
+    )]
+    columns: Option<String>,
+    #[clap(long = "sort", possible_values = output::SORT_KEYS, about = "Sort records by this field")]
+    sort: Option<SortKey>,
+    #[clap(long = "reverse", about = "Reverse the sort order")]
+    reverse: bool,
+    #[clap(long = "no-header", about = "Leave out the table header")]
+    no_header: bool,
+    #[clap(
+        short = "w",
+        long = "wide",
+        about = "Don't truncate long content to fit the terminal"
+    )]
+    wide: bool,
+}
+
+impl ShowOpts {
+    fn filter(&self) -> Result<Filter> {
+        Ok(Filter {
+            types: Filter::parse_types(&self.types)?,
+            name: self.name.as_deref().map(filter::name_pattern).transpose()?,
+            content: self
+                .content
+                .as_deref()
+                .map(ContentMatch::parse)
+                .transpose()?,
+            proxied: match (self.proxied, self.dns_only) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            comment: self.comment.as_ref().map(|c| c.to_lowercase()),
+            tags: self.tags.clone(),
+        })
+    }
+
+    /// The part of the filter and sort order Cloudflare can apply itself,
+    /// as query parameters. The full filter is still checked locally.
+    fn query(&self, zone: &Zone) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+        if let Some(name) = self.name.as_deref() {
+            if !name.contains(['*', '?', '/']) {
+                query.push(("name", zone.qualify(name)));
+            }
+        }
+        if !self.types.eq_ignore_ascii_case("all") && !self.types.contains(',') {
+            query.push(("type", self.types.trim().to_uppercase()));
+        }
+        if self.proxied || self.dns_only {
+            query.push(("proxied", self.proxied.to_string()));
+        }
+        if !query.is_empty() {
+            query.push(("match", "all".to_owned()));
+        }
+        match self.sort {
+            Some(SortKey::Name) => query.push(("order", "name".to_owned())),
+            Some(SortKey::Type) => query.push(("order", "type".to_owned())),
+            Some(SortKey::Content) => query.push(("order", "content".to_owned())),
+            Some(SortKey::Modified) | None => return query,
+        }
+        let direction = if self.reverse { "desc" } else { "asc" };
+        query.push(("direction", direction.to_owned()));
+        query
+    }
 }
 
 #[derive(Clap)]
@@ -58,144 +398,1340 @@ struct SetOpts {
         about = "Destination of the record to set"
     )]
     dest: String,
-    #[clap(default_value = "A", about = "DNS type of the record to set")]
-    r#type: String,
+    #[clap(
+        parse(from_str = uppercase),
+        about = "DNS type of the record to set [default: A, or SRV or CAA given their options]"
+    )]
+    r#type: Option<String>,
+    #[clap(long = "ttl", about = "TTL in seconds, 1 for automatic")]
+    ttl: Option<u32>,
+    #[clap(
+        long = "proxied",
+        conflicts_with = "no-proxied",
+        about = "Proxy the record through Cloudflare"
+    )]
+    proxied: bool,
+    #[clap(long = "no-proxied", about = "Make the record DNS only")]
+    no_proxied: bool,
+    #[clap(
+        long = "priority",
+        about = "Priority of MX, URI, SRV, HTTPS and SVCB records"
+    )]
+    priority: Option<u16>,
+    #[clap(long = "comment", about = "Comment to attach to the record")]
+    comment: Option<String>,
+    #[clap(
+        long = "tag",
+        number_of_values = 1,
+        about = "Tag to attach to the record (repeatable)"
+    )]
+    tags: Vec<String>,
+    #[clap(long = "srv-service", about = "Service of an SRV record (e.g. _sip)")]
+    srv_service: Option<String>,
+    #[clap(long = "srv-proto", about = "Protocol of an SRV record (e.g. _tcp)")]
+    srv_proto: Option<String>,
+    #[clap(long = "srv-port", about = "Port of an SRV record")]
+    srv_port: Option<String>,
+    #[clap(long = "srv-weight", about = "Weight of an SRV record")]
+    srv_weight: Option<String>,
+    #[clap(long = "srv-target", about = "Target host of an SRV record")]
+    srv_target: Option<String>,
+    #[clap(long = "caa-flags", about = "Flags of a CAA record")]
+    caa_flags: Option<String>,
+    #[clap(
+        long = "caa-tag",
+        about = "Tag of a CAA record (issue, issuewild, iodef)"
+    )]
+    caa_tag: Option<String>,
+    #[clap(long = "caa-value", about = "Value of a CAA record")]
+    caa_value: Option<String>,
+    #[clap(
+        long = "data",
+        number_of_values = 1,
+        about = "Field of structured record data as KEY=VALUE (repeatable)"
+    )]
+    data: Vec<String>,
+    #[clap(
+        long = "content",
+        about = "Update the record that currently has this content"
+    )]
+    content: Option<String>,
+    #[clap(long = "id", about = "Update the record with this ID")]
+    id: Option<String>,
+    #[clap(
+        long = "add",
+        conflicts_with_all = &["content", "id"],
+        about = "Add a record alongside existing ones instead of replacing them"
+    )]
+    add: bool,
+}
+
+impl SetOpts {
+    fn proxied(&self) -> Option<bool> {
+        match (self.proxied, self.no_proxied) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+
+    fn srv_fields(&self) -> Vec<(&str, &String)> {
+        let fields = vec![
+            ("port", &self.srv_port),
+            ("weight", &self.srv_weight),
+            ("target", &self.srv_target),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+            .collect()
+    }
+
+    fn caa_fields(&self) -> Vec<(&str, &String)> {
+        let fields = vec![
+            ("flags", &self.caa_flags),
+            ("tag", &self.caa_tag),
+            ("value", &self.caa_value),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+            .collect()
+    }
+
+    /// The DNS type to create, inferred from the SRV/CAA options if no
+    /// type was given.
+    fn record_type(&self) -> &str {
+        match &self.r#type {
+            Some(r#type) => r#type,
+            None if self.srv_service.is_some() || !self.srv_fields().is_empty() => "SRV",
+            None if !self.caa_fields().is_empty() => "CAA",
+            None => "A",
+        }
+    }
+
+    /// The SRV service and protocol labels, which only make sense together.
+    fn srv_labels(&self) -> Result<Option<(String, String)>> {
+        let label = |l: &str| format!("_{}", l.trim_start_matches('_'));
+        match (&self.srv_service, &self.srv_proto) {
+            (Some(service), Some(proto)) => Ok(Some((label(service), label(proto)))),
+            (None, None) => Ok(None),
+            _ => Err(
+                Invalid("--srv-service and --srv-proto must be given together".to_owned()).into(),
+            ),
+        }
+    }
+
+    /// The fully qualified record name, with the SRV service and protocol
+    /// prepended.
+    fn record_name(&self, zone: &Zone) -> Result<String> {
+        let name = zone.qualify(&self.name);
+        Ok(match self.srv_labels()? {
+            Some((service, proto)) => format!("{}.{}.{}", service, proto, name),
+            None => name,
+        })
+    }
 }
 
 #[derive(Clap)]
 struct DelOpts {
     #[clap(about = "Name of the record to delete")]
     name: String,
+    #[clap(
+        long = "type",
+        parse(from_str = uppercase),
+        about = "Only delete records of this DNS type"
+    )]
+    r#type: Option<String>,
+    #[clap(long = "content", about = "Only delete records with this content")]
+    content: Option<String>,
+    #[clap(long = "id", about = "Only delete the record with this ID")]
+    id: Option<String>,
+    #[clap(long = "all", about = "Delete every matching record, not just one")]
+    all: bool,
 }
 
-#[derive(Deserialize)]
-struct Response {
-    result: Vec<Entry>,
+/// DNS types are matched and sent to Cloudflare in upper case.
+fn uppercase(s: &str) -> String {
+    s.to_uppercase()
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct Entry {
-    id: String,
-    name: String,
-    r#type: String,
-    content: String,
-}
+fn show_rec(zones: &[(Zone, Vec<Entry>)], opts: &ShowOpts) -> Result<()> {
+    let filter = opts.filter()?;
+    let mut rows = Vec::new();
+    for (zone, records) in zones {
+        for entry in records {
+            if !filter.matches(entry, zone) {
+                continue;
+            };
+            rows.push(Row {
+                zone: &zone.name,
+                name: if opts.relative {
+                    zone.relative(&entry.name)
+                } else {
+                    entry.name.clone()
+                },
+                entry,
+            });
+        }
+    }
+
+    if let Some(key) = opts.sort {
+        output::sort(&mut rows, key, opts.reverse);
+    } else if opts.reverse {
+        rows.reverse();
+    }
+
+    let columns = match &opts.columns {
+        Some(columns) => Column::parse_list(columns)?,
+        None => {
+            let mut columns = Column::parse_list(&output::DEFAULT_COLUMNS.join(","))?;
+            if opts.all_zones {
+                columns.insert(0, Column::Zone);
+            }
+            columns
+        }
+    };
+    let table = Table {
+        columns,
+        header: !opts.no_header,
+        truncate: !opts.wide,
+    };
 
-fn show_rec(records: &Vec<Entry>, filter: &str) -> Result<()> {
     let stdout = std::io::stdout();
-    let mut tw = TabWriter::new(stdout.lock());
-    let mut line = String::new();
-    for entry in records {
-        if filter != "all" && entry.r#type != filter {
-            continue;
-        };
-        writeln!(
-            &mut line,
-            "{}\t{}\t{}",
-            entry.r#type, entry.name, entry.content
-        )?;
-        tw.write_all(&line.as_bytes())?;
-        line.clear();
+    output::write(&mut stdout.lock(), &opts.output, &rows, &table)
+}
+
+fn list_zones(opts: &ZonesOpts, settings: &Settings) -> Result<()> {
+    match opts.subcmd {
+        ZonesSubcommand::List => {
+            let zones = Zone::list(&settings.token)?;
+            let stdout = std::io::stdout();
+            let mut tw = TabWriter::new(stdout.lock());
+            for zone in &zones {
+                writeln!(
+                    &mut tw,
+                    "{}\t{}\t{}\t{}\t{}",
+                    zone.name,
+                    zone.id,
+                    zone.status,
+                    zone.plan.name,
+                    zone.name_servers.join(",")
+                )?;
+            }
+            tw.flush()?;
+        }
     }
-    tw.flush()?;
 
     Ok(())
 }
 
-fn del_rec(records: &Vec<Entry>, name: &str) -> Result<()> {
-    match find_rec(records, name) {
-        Some(entry) => {
-            let resp = ureq::delete(&record_endpoint(&entry.id))
-                .set("Content-Type", "application/json")
-                .set("Authorization", &format!("Bearer {}", *TOKEN))
-                .call();
-            if resp.ok() {
-                println!("Successfully deleted {}", entry.name);
-            } else {
-                println!("Error deleting {}", entry.name);
+fn export_zone(zone: &Zone, opts: &ExportOpts, settings: &Settings) -> Result<()> {
+    let (text, count) = if opts.cloudflare {
+        let text = api::call_text(
+            ureq::get(&format!("{}/export", zone.records_endpoint()))
+                .set("Authorization", &format!("Bearer {}", settings.token)),
+        )
+        .context("Failed to export zone")?;
+        (text, None)
+    } else {
+        let records = list_rec(zone, settings, &[])?;
+        let mut text = Vec::new();
+        zonefile::write(&mut text, zone, &records)?;
+        (String::from_utf8(text)?, Some(records.len()))
+    };
+
+    match &opts.output {
+        Some(path) => {
+            fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+            match count {
+                Some(count) => println!(
+                    "Exported {} records of {} to {}",
+                    count,
+                    zone.name,
+                    path.display()
+                ),
+                None => println!("Exported {} to {}", zone.name, path.display()),
+            }
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn check_config(opts: &ConfigOpts, settings: &Settings) -> Result<()> {
+    match opts.subcmd {
+        ConfigSubcommand::Check => {
+            let stdout = std::io::stdout();
+            let mut tw = TabWriter::new(stdout.lock());
+            let config = match &settings.config_path {
+                Some(path) => path.display().to_string(),
+                None => "(none)".to_owned(),
+            };
+            writeln!(&mut tw, "config file:\t{}", config)?;
+            writeln!(
+                &mut tw,
+                "profile:\t{}",
+                settings.profile.as_deref().unwrap_or("(none)")
+            )?;
+            writeln!(&mut tw, "token:\tfrom {}", settings.token_source)?;
+            writeln!(
+                &mut tw,
+                "zone:\t{}",
+                settings
+                    .zone
+                    .as_deref()
+                    .unwrap_or("(inferred from record names)")
+            )?;
+            writeln!(
+                &mut tw,
+                "default ttl:\t{}",
+                match settings.ttl {
+                    TTL_AUTO => "auto".to_owned(),
+                    ttl => ttl.to_string(),
+                }
+            )?;
+            writeln!(&mut tw, "default proxied:\t{}", settings.proxied)?;
+            writeln!(&mut tw, "ip url:\t{}", settings.ip_url)?;
+            writeln!(&mut tw, "ipv6 url:\t{}", settings.ipv6_url)?;
+            tw.flush()?;
+
+            let status = token::verify(&settings.token)?;
+            println!(
+                "Token {} is {} (valid from {}, {})",
+                status.id,
+                status.status,
+                status.not_before.as_deref().unwrap_or("creation"),
+                match &status.expires_on {
+                    Some(expires_on) => format!("until {}", expires_on),
+                    None => "with no expiry".to_owned(),
+                }
+            );
+            if let Some(zone) = &settings.zone {
+                let zone = Zone::lookup(zone, &settings.token)?;
+                println!("Zone {} ({}) is accessible", zone.name, zone.id);
             }
         }
-        _ => println!("No such record exists"),
     }
 
     Ok(())
 }
 
-fn set_rec(records: &Vec<Entry>, name: &str, dest: &str, r#type: &str) -> Result<()> {
-    let destination = match dest {
-        "this_machine_ip" => {
-            let resp = ureq::get("https://ipinfo.io/ip").call().into_string()?;
-            resp.trim().to_owned()
+fn verify_token(opts: &TokenOpts, settings: &Settings) -> Result<()> {
+    match opts.subcmd {
+        TokenSubcommand::Verify => {
+            let info = token::refresh(&settings.token)?;
+            println!("Token {} is {}", info.status.id, info.status.status);
+            println!(
+                "Valid from {}, {}",
+                info.status.not_before.as_deref().unwrap_or("creation"),
+                match &info.status.expires_on {
+                    Some(expires_on) => format!("until {}", expires_on),
+                    None => "with no expiry".to_owned(),
+                }
+            );
+
+            let policies = match &info.policies {
+                Some(policies) => policies,
+                None => {
+                    println!(
+                        "Permissions are unavailable, the token needs the API Tokens Read permission to list them"
+                    );
+                    return Ok(());
+                }
+            };
+            let zones = zone::cached(&settings.token);
+            let stdout = std::io::stdout();
+            let mut tw = TabWriter::new(stdout.lock());
+            writeln!(&mut tw, "\nEFFECT\tPERMISSIONS\tRESOURCES")?;
+            for policy in policies {
+                let groups: Vec<_> = policy
+                    .permission_groups
+                    .iter()
+                    .map(|g| g.name.as_str())
+                    .collect();
+                let resources: Vec<_> = policy
+                    .resources
+                    .keys()
+                    .map(|key| {
+                        let id = key.trim_start_matches("com.cloudflare.api.account.zone.");
+                        match zones.iter().find(|z| z.id == id) {
+                            Some(zone) => format!("{} ({})", key, zone.name),
+                            None => key.clone(),
+                        }
+                    })
+                    .collect();
+                writeln!(
+                    &mut tw,
+                    "{}\t{}\t{}",
+                    policy.effect,
+                    groups.join(", "),
+                    resources.join(", ")
+                )?;
+            }
+            tw.flush()?;
         }
-        _ => dest.to_owned(),
+    }
+
+    Ok(())
+}
+
+fn del_rec(zone: &Zone, opts: &DelOpts, settings: &Settings) -> Result<()> {
+    let name = zone.qualify(&opts.name);
+    let selector = Selector {
+        name: &name,
+        r#type: opts.r#type.as_deref(),
+        content: opts.content.as_deref(),
+        id: opts.id.as_deref(),
+    };
+    let records = list_rec(zone, settings, &selector.query())?;
+    let matches = find_rec(&records, &selector);
+    if matches.is_empty() {
+        return Err(NotFound(format!("No such record exists: {}", name)).into());
+    }
+    if matches.len() > 1 && !opts.all {
+        return Err(ambiguous(
+            &name,
+            &matches,
+            "narrow it down with --type, --content or --id, or pass --all",
+        ));
+    }
+    confirm::check_deletes(matches.len(), settings)?;
+    let action = match matches.len() {
+        1 => "Delete this record".to_owned(),
+        n => format!("Delete these {} records", n),
+    };
+    confirm::confirm(&action, &matches, settings)?;
+
+    for entry in matches {
+        if delete_rec(zone, entry, settings)? {
+            println!(
+                "Successfully deleted {} ({} {})",
+                entry.name,
+                entry.r#type,
+                entry.display_content()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn set_rec(zone: &Zone, opts: &SetOpts, settings: &Settings) -> Result<()> {
+    let name = opts.record_name(zone)?;
+    let name = name.as_str();
+    let selector = Selector {
+        name,
+        // An ID pins down the record on its own, whatever its type.
+        r#type: match opts.id {
+            Some(_) => None,
+            None => Some(opts.record_type()),
+        },
+        content: opts.content.as_deref(),
+        id: opts.id.as_deref(),
     };
+    let records = list_rec(zone, settings, &selector.query())?;
+    let matches = find_rec(&records, &selector);
 
-    match find_rec(records, name) {
-        Some(entry) => {
+    match matches.as_slice() {
+        [entry] if !opts.add => {
+            let mut new = RecordBody::from(*entry);
+            apply_set_opts(&mut new, opts, settings)?;
+            new.validate()?;
+            let patch = RecordPatch::between(entry, &new);
+            if patch.is_empty() {
+                println!(
+                    "{} is unchanged ({} {})",
+                    name,
+                    new.r#type,
+                    new.display_content()
+                );
+                return Ok(());
+            }
             println!("{} already exists, trying to update...", name);
-            let new = Entry {
-                id: entry.id.clone(),
-                content: destination,
-                name: entry.name.clone(),
-                r#type: entry.r#type.clone(),
-            };
-            let resp = ureq::put(&record_endpoint(&entry.id))
-                .set("Content-Type", "application/json")
-                .set("Authorization", &format!("Bearer {}", *TOKEN))
-                .send_json(serde_json::from_str(&serde_json::to_string(&new)?)?);
-            if resp.ok() {
+            confirm::confirm(
+                &format!("Overwrite it with {} {}", new.r#type, new.display_content()),
+                &[entry],
+                settings,
+            )?;
+            if let Some(updated) = update_rec(zone, entry, &patch, settings)? {
                 println!(
                     "Successfully Updated {} with {} (type: {})",
-                    name, new.content, new.r#type
+                    name,
+                    updated.display_content(),
+                    updated.r#type
                 );
-            } else {
-                println!("Error updating {}", name);
             }
         }
 
+        [_, _, ..] if !opts.add => {
+            return Err(ambiguous(
+                name,
+                &matches,
+                "narrow it down with --content or --id, or pass --add",
+            ));
+        }
+
         _ => {
-            println!("No such record exists, trying to create new...");
-            let new = Entry {
-                id: "".to_owned(),
-                content: destination,
-                name: name.to_owned(),
-                r#type: r#type.to_owned(),
-            };
-            let resp = ureq::post(&*ENDPOINT)
-                .set("Content-Type", "application/json")
-                .set("Authorization", &format!("Bearer {}", *TOKEN))
-                .send_json(serde_json::from_str(&serde_json::to_string(&new)?)?);
-            if resp.ok() {
-                println!("Successfully Updated {} to point to {}", name, new.content);
+            if opts.content.is_some() || opts.id.is_some() {
+                return Err(NotFound(format!("No such record exists: {}", name)).into());
+            }
+            let mut new = RecordBody::new(name, opts.record_type());
+            new.ttl = settings.ttl;
+            new.proxied = settings.proxied_for(&new.r#type);
+            apply_set_opts(&mut new, opts, settings)?;
+            new.validate()?;
+            if matches
+                .iter()
+                .any(|entry| entry.display_content() == new.display_content())
+            {
+                println!(
+                    "{} is unchanged ({} {})",
+                    name,
+                    new.r#type,
+                    new.display_content()
+                );
+                return Ok(());
+            }
+            if matches.is_empty() {
+                println!("No such record exists, trying to create new...");
             } else {
-                println!("Error updating {}", name);
+                println!("Adding another {} record for {}...", new.r#type, name);
+            }
+            if let Some(created) = create_rec(zone, &new, settings)? {
+                println!(
+                    "Successfully Updated {} to point to {}",
+                    name,
+                    created.display_content()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn import_zone(zone: &Zone, opts: &ImportOpts, settings: &Settings) -> Result<()> {
+    let mut file = zonefile::parse(&opts.file, zone)?;
+
+    let mut problems = Vec::new();
+    for parsed in &mut file.records {
+        let r#type = parsed.body.r#type.clone();
+        let default = match (opts.proxied, opts.no_proxied) {
+            (true, _) => record::PROXIABLE_TYPES.contains(&r#type.as_str()),
+            (_, true) => false,
+            _ => settings.proxied_for(&r#type),
+        };
+        parsed.body.proxied =
+            parsed.proxied.unwrap_or(default) && record::PROXIABLE_TYPES.contains(&r#type.as_str());
+        if parsed.body.proxied {
+            parsed.body.ttl = TTL_AUTO;
+        }
+        if let Err(err) = zonefile::validate(parsed) {
+            problems.push(format!("{:#}", err));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(Invalid(format!(
+            "Cannot import {}:\n  - {}",
+            opts.file.display(),
+            problems.join("\n  - ")
+        ))
+        .into());
+    }
+
+    let existing = list_rec(zone, settings, &[])?;
+    let (present, missing): (Vec<_>, Vec<_>) = file
+        .records
+        .iter()
+        .partition(|p| existing.iter().any(|e| zonefile::same_record(e, &p.body)));
+
+    println!("Importing {} into {}:", opts.file.display(), zone.name);
+    let stdout = std::io::stdout();
+    let mut tw = TabWriter::new(stdout.lock());
+    for (mark, parsed) in missing
+        .iter()
+        .map(|p| ("+", p))
+        .chain(present.iter().map(|p| ("=", p)))
+    {
+        let body = &parsed.body;
+        writeln!(
+            &mut tw,
+            "  {} {}\t{}\t{}\t{}",
+            mark,
+            body.name,
+            body.r#type,
+            body.display_content(),
+            if body.proxied { "proxied" } else { "dns-only" }
+        )?;
+    }
+    tw.flush()?;
+    drop(tw);
+    if !file.skipped.is_empty() {
+        println!(
+            "Skipping {} SOA and apex NS records, which Cloudflare manages itself",
+            file.skipped.len()
+        );
+    }
+    println!(
+        "{} to create, {} already present",
+        missing.len(),
+        present.len()
+    );
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut created = 0;
+    let mut failed = Vec::new();
+    for (i, parsed) in missing.iter().enumerate() {
+        let body = &parsed.body;
+        match create_rec(zone, body, settings) {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                created += 1;
+                println!(
+                    "[{}/{}] Created {} {} {}",
+                    i + 1,
+                    missing.len(),
+                    body.name,
+                    body.r#type,
+                    body.display_content()
+                )
+            }
+            Err(err) => {
+                eprintln!(
+                    "[{}/{}] {:#} ({} {})",
+                    i + 1,
+                    missing.len(),
+                    err,
+                    body.r#type,
+                    body.display_content()
+                );
+                failed.push(err);
+            }
+        }
+    }
+
+    if created > 0 || !failed.is_empty() {
+        println!("Created {} of {} records", created, missing.len());
+    }
+    match failed.into_iter().next() {
+        Some(err) => Err(err.context(format!(
+            "Some records of {} failed to import",
+            opts.file.display()
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn plan_zone(desired: &Desired, opts: &PlanOpts, apply: bool, settings: &Settings) -> Result<()> {
+    let zone = resolve_zone(settings, desired.zone.as_deref())?;
+    if let Some(name) = &desired.zone {
+        if !zone.name.eq_ignore_ascii_case(name.trim_end_matches('.')) {
+            return Err(Invalid(format!(
+                "{} is for zone {}, not {}",
+                opts.file.display(),
+                name,
+                zone.name
+            ))
+            .into());
+        }
+    }
+    let bodies = desired.bodies(&zone, settings)?;
+    if apply {
+        token::preflight(&settings.token, &zone)?;
+    }
+
+    let live = list_rec(&zone, settings, &[])?;
+    let changes = plan::diff(&live, bodies, opts.prune);
+    if changes.is_empty() {
+        println!("{} is up to date, no changes needed.", zone.name);
+        return Ok(());
+    }
+    plan::print(&changes)?;
+    if !apply {
+        return Ok(());
+    }
+    apply_changes(&zone, changes, settings)
+}
+
+/// Make the changes of a plan, after checking and asking.
+fn apply_changes(zone: &Zone, mut changes: Vec<Change>, settings: &Settings) -> Result<()> {
+    let deletes = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Delete(_)))
+        .count();
+    confirm::check_deletes(deletes, settings)?;
+    confirm::confirm("Apply these changes", &[], settings)?;
+
+    // Deleting first makes room for CNAMEs, which can't share a name.
+    changes.sort_by_key(|change| match change {
+        Change::Delete(_) => 0,
+        Change::Update(..) => 1,
+        Change::Create(_) => 2,
+    });
+    println!();
+    let mut applied = 0;
+    for change in &changes {
+        match change {
+            Change::Create(body) => {
+                if let Some(created) = create_rec(zone, body, settings)? {
+                    println!(
+                        "Created {} {} {}",
+                        created.name,
+                        created.r#type,
+                        created.display_content()
+                    );
+                    applied += 1;
+                }
+            }
+            Change::Update(entry, _, patch) => {
+                if let Some(updated) = update_rec(zone, entry, patch, settings)? {
+                    println!(
+                        "Updated {} {} {}",
+                        updated.name,
+                        updated.r#type,
+                        updated.display_content()
+                    );
+                    applied += 1;
+                }
+            }
+            Change::Delete(entry) => {
+                if delete_rec(zone, entry, settings)? {
+                    println!(
+                        "Deleted {} {} {}",
+                        entry.name,
+                        entry.r#type,
+                        entry.display_content()
+                    );
+                    applied += 1;
+                }
             }
         }
     }
+    if applied > 0 {
+        println!("Applied {} changes to {}", applied, zone.name);
+    }
+    Ok(())
+}
+
+fn backup_zone(zone: &Zone, opts: &BackupOpts, settings: &Settings) -> Result<()> {
+    let dir = snapshot::dir(opts.dir.as_deref(), &zone.name)?;
+    if opts.list {
+        let stdout = std::io::stdout();
+        let mut tw = TabWriter::new(stdout.lock());
+        for path in snapshot::list(&dir)? {
+            let snapshot = snapshot::load(&path)?;
+            writeln!(
+                &mut tw,
+                "{}\t{}\t{} records",
+                path.display(),
+                snapshot.taken_at,
+                snapshot.records.len()
+            )?;
+        }
+        tw.flush()?;
+        return Ok(());
+    }
 
+    let records = list_rec(zone, settings, &[])?;
+    let count = records.len();
+    let path = snapshot::save(&dir, zone, records)?;
+    println!(
+        "Saved {} records of {} to {}",
+        count,
+        zone.name,
+        path.display()
+    );
+    if let Some(keep) = opts.keep {
+        for old in snapshot::prune(&dir, keep)? {
+            println!("Deleted old snapshot {}", old.display());
+        }
+    }
     Ok(())
 }
 
-fn find_rec<'a>(records: &'a Vec<Entry>, name: &str) -> Option<&'a Entry> {
-    records.iter().find(|&entry| entry.name == name)
+fn restore_zone(opts: &RestoreOpts, settings: &Settings) -> Result<()> {
+    let zone = match &settings.zone {
+        Some(_) => Some(resolve_zone(settings, None)?),
+        None => None,
+    };
+    let dir = match &zone {
+        Some(zone) => Some(snapshot::dir(opts.dir.as_deref(), &zone.name)?),
+        None => None,
+    };
+    let path = snapshot::find(&opts.snapshot, dir.as_deref())?;
+    let snapshot = snapshot::load(&path)?;
+    let zone = match zone {
+        Some(zone) if zone.id != snapshot.zone.id => {
+            return Err(Invalid(format!(
+                "{} is a snapshot of {}, not {}",
+                path.display(),
+                snapshot.zone.name,
+                zone.name
+            ))
+            .into())
+        }
+        Some(zone) => zone,
+        None => snapshot.zone.clone(),
+    };
+    token::preflight(&settings.token, &zone)?;
+
+    let live = list_rec(&zone, settings, &[])?;
+    let desired = snapshot.records.iter().map(RecordBody::from).collect();
+    let changes = plan::diff(&live, desired, true);
+    if changes.is_empty() {
+        println!(
+            "{} already matches the snapshot taken at {}",
+            zone.name, snapshot.taken_at
+        );
+        return Ok(());
+    }
+    println!(
+        "Restoring {} to the snapshot taken at {}:",
+        zone.name, snapshot.taken_at
+    );
+    plan::print(&changes)?;
+    apply_changes(&zone, changes, settings)
+}
+
+fn show_history(opts: &HistoryOpts, zone: Option<&str>) -> Result<()> {
+    let ops = journal::load()?;
+    let undone: Vec<u64> = ops.iter().filter_map(|op| op.undoes).collect();
+    let ops: Vec<_> = ops
+        .iter()
+        .filter(|op| {
+            zone.is_none_or(|zone| {
+                let zone = zone.trim_end_matches('.');
+                op.zone.eq_ignore_ascii_case(zone) || op.zone_id == zone
+            })
+        })
+        .collect();
+
+    let stdout = std::io::stdout();
+    let mut tw = TabWriter::new(stdout.lock());
+    writeln!(&mut tw, "SEQ\tTIME\tUSER\tZONE\tACTION\tTYPE\tNAME\tCHANGE")?;
+    for op in ops.iter().skip(ops.len().saturating_sub(opts.limit)) {
+        let record = match op.record() {
+            Some(record) => record,
+            None => continue,
+        };
+        let action = match op.undoes {
+            Some(seq) => format!("{} (undo #{})", op.action.name(), seq),
+            None if undone.contains(&op.seq) => format!("{} (undone)", op.action.name()),
+            None => op.action.name().to_owned(),
+        };
+        let change = match (&op.before, &op.after) {
+            (Some(before), Some(after)) => format!(
+                "{} -> {}",
+                before.display_content(),
+                after.display_content()
+            ),
+            (None, Some(after)) => format!("+ {}", after.display_content()),
+            (Some(before), None) => format!("- {}", before.display_content()),
+            (None, None) => String::new(),
+        };
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            op.seq, op.time, op.user, op.zone, action, record.r#type, record.name, change
+        )?;
+    }
+    tw.flush()?;
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let conf: Config = Config::parse();
+fn undo(opts: &UndoOpts, settings: &Settings) -> Result<()> {
+    let ops = journal::load()?;
+    let todo = journal::undoable(&ops, opts.count);
+    if todo.is_empty() {
+        println!("Nothing to undo");
+        return Ok(());
+    }
+
+    let mut zones: HashMap<String, Zone> = HashMap::new();
+    for op in &todo {
+        if !zones.contains_key(&op.zone_id) {
+            let zone = Zone::lookup(&op.zone_id, &settings.token)?;
+            token::preflight(&settings.token, &zone)?;
+            zones.insert(op.zone_id.clone(), zone);
+        }
+    }
+
+    let (steps, conflicts) =
+        journal::plan_undo(todo, |op, id| get_rec(&zones[&op.zone_id], id, settings))?;
+    if !conflicts.is_empty() && !opts.force {
+        return Err(Invalid(format!(
+            "Records have changed since, pass --force to undo anyway:\n  - {}",
+            conflicts.join("\n  - ")
+        ))
+        .into());
+    }
 
-    let resp: Response = serde_json::from_str(
-        &ureq::get(&ENDPOINT)
+    println!("Undoing:");
+    for (op, current) in &steps {
+        let line = match (op.action, current, &op.before) {
+            (Action::Create, Some(current), _) => format!(
+                "- {} {} {}",
+                current.name,
+                current.r#type,
+                current.display_content()
+            ),
+            (Action::Update, Some(current), Some(before)) => format!(
+                "~ {} {} {} -> {}",
+                current.name,
+                current.r#type,
+                current.display_content(),
+                before.display_content()
+            ),
+            (Action::Delete, None, Some(before)) => format!(
+                "+ {} {} {}",
+                before.name,
+                before.r#type,
+                before.display_content()
+            ),
+            _ => "skipped, the record has changed since".to_owned(),
+        };
+        println!("  {}  (#{} {} {})", line, op.seq, op.action.name(), op.time);
+    }
+    let deletes = steps
+        .iter()
+        .filter(|(op, current)| op.action == Action::Create && current.is_some())
+        .count();
+    confirm::check_deletes(deletes, settings)?;
+    confirm::confirm(
+        &format!("Undo these {} operations", steps.len()),
+        &[],
+        settings,
+    )?;
+
+    // Records created again get new IDs, which older operations must use.
+    let mut ids: HashMap<String, String> = HashMap::new();
+    for (op, current) in steps {
+        let zone = &zones[&op.zone_id];
+        let current = current.map(|mut entry| {
+            if let Some(id) = ids.get(&entry.id) {
+                entry.id = id.clone();
+            }
+            entry
+        });
+
+        match (op.action, current, &op.before) {
+            (Action::Create, Some(current), _) => {
+                if !send_delete(zone, &current, settings)? {
+                    continue;
+                }
+                journal::append(zone, Action::Delete, Some(&current), None, Some(op.seq));
+                println!(
+                    "Deleted {} {} {}",
+                    current.name,
+                    current.r#type,
+                    current.display_content()
+                );
+            }
+            (Action::Update, Some(current), Some(before)) => {
+                let patch = RecordPatch::between(&current, &RecordBody::from(before));
+                if patch.is_empty() {
+                    continue;
+                }
+                if let Some(updated) = send_update(zone, &current, &patch, settings)? {
+                    journal::append(
+                        zone,
+                        Action::Update,
+                        Some(&current),
+                        Some(&updated),
+                        Some(op.seq),
+                    );
+                    println!(
+                        "Restored {} {} {}",
+                        updated.name,
+                        updated.r#type,
+                        updated.display_content()
+                    );
+                }
+            }
+            (Action::Delete, None, Some(before)) => {
+                if let Some(created) = send_create(zone, &RecordBody::from(before), settings)? {
+                    ids.insert(before.id.clone(), created.id.clone());
+                    journal::append(zone, Action::Create, None, Some(&created), Some(op.seq));
+                    println!(
+                        "Created {} {} {}",
+                        created.name,
+                        created.r#type,
+                        created.display_content()
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The error for a selector that matched several records where one was needed.
+fn ambiguous(name: &str, matches: &[&Entry], hint: &str) -> anyhow::Error {
+    let mut msg = format!("{} matches {} records, {}:", name, matches.len(), hint);
+    for entry in matches {
+        let _ = write!(
+            &mut msg,
+            "\n  {}\t{}\t{}",
+            entry.id,
+            entry.r#type,
+            entry.display_content()
+        );
+    }
+    Invalid(msg).into()
+}
+
+/// Override the fields of `body` that were given on the command line,
+/// leaving the rest as they are.
+fn apply_set_opts(body: &mut RecordBody, opts: &SetOpts, settings: &Settings) -> Result<()> {
+    if data::is_structured(&body.r#type) {
+        apply_data_opts(body, opts)?;
+    } else {
+        // Nothing to put these in, so don't let them go unnoticed.
+        let srv = opts.srv_fields().into_iter().map(|(key, _)| ("srv", key));
+        let caa = opts.caa_fields().into_iter().map(|(key, _)| ("caa", key));
+        let mut given: Vec<_> = srv
+            .chain(caa)
+            .map(|(kind, key)| format!("--{}-{}", kind, key))
+            .collect();
+        if !opts.data.is_empty() {
+            given.push("--data".to_owned());
+        }
+        if !given.is_empty() {
+            return Err(Invalid(format!(
+                "{} records have no structured data, so {} cannot be used",
+                body.r#type,
+                given.join(", ")
+            ))
+            .into());
+        }
+        body.content = match opts.dest.as_str() {
+            "this_machine_ip" => this_machine_ip(match body.r#type.as_str() {
+                "AAAA" => &settings.ipv6_url,
+                _ => &settings.ip_url,
+            })?,
+            dest => dest.to_owned(),
+        };
+        if opts.priority.is_some() {
+            body.priority = opts.priority;
+        }
+    }
+    if let Some(ttl) = opts.ttl {
+        body.ttl = ttl;
+    }
+    if let Some(proxied) = opts.proxied() {
+        body.proxied = proxied;
+    }
+    if opts.comment.is_some() {
+        body.comment = opts.comment.clone();
+    }
+    if !opts.tags.is_empty() {
+        body.tags = opts.tags.clone();
+    }
+    Ok(())
+}
+
+/// Build the structured data of `body` from the destination (given in zone
+/// file format) and the per-field options.
+fn apply_data_opts(body: &mut RecordBody, opts: &SetOpts) -> Result<()> {
+    let r#type = body.r#type.clone();
+    let mut data = match (opts.dest.as_str(), body.data.take()) {
+        ("this_machine_ip", Some(serde_json::Value::Object(data))) => data,
+        ("this_machine_ip", _) => serde_json::Map::new(),
+        (dest, _) => data::parse(&r#type, dest)?,
+    };
+
+    if let Some(priority) = opts.priority {
+        if data::has_priority(&r#type) {
+            data.insert("priority".to_owned(), priority.into());
+        } else {
+            body.priority = Some(priority);
+        }
+    }
+    for (key, value) in opts.srv_fields().into_iter().chain(opts.caa_fields()) {
+        data::set_field(&mut data, &r#type, key, value)?;
+    }
+    for pair in &opts.data {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => data::set_field(&mut data, &r#type, key, value)?,
+            _ => return Err(Invalid(format!("Expected KEY=VALUE, got {}", pair)).into()),
+        }
+    }
+
+    body.data = Some(serde_json::Value::Object(data));
+    Ok(())
+}
+
+fn this_machine_ip(url: &str) -> Result<String> {
+    let resp = ureq::get(url).call();
+    if let Some(err) = resp.synthetic_error() {
+        return Err(api::NetworkError(err.to_string()))
+            .context("Failed to look up this machine's IP");
+    }
+    Ok(resp.into_string()?.trim().to_owned())
+}
+
+fn find_rec<'a>(records: &'a [Entry], selector: &Selector) -> Vec<&'a Entry> {
+    records
+        .iter()
+        .filter(|&entry| selector.matches(entry))
+        .collect()
+}
+
+/// A record by its ID, or nothing if it no longer exists.
+fn get_rec(zone: &Zone, id: &str, settings: &Settings) -> Result<Option<Entry>> {
+    let resp = api::call::<Entry>(
+        ureq::get(&zone.record_endpoint(id))
             .set("Content-Type", "application/json")
-            .set("Authorization", &format!("Bearer {}", *TOKEN))
-            .call()
-            .into_string()?,
+            .set("Authorization", &format!("Bearer {}", settings.token)),
+        None,
+    );
+    match resp {
+        Ok(resp) => Ok(resp.result),
+        Err(err)
+            if err
+                .downcast_ref::<api::ApiError>()
+                .is_some_and(|err| err.status == 404) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err.context(format!("Failed to look up record {}", id))),
+    }
+}
+
+/// List the records of `zone` that match the query parameters, which
+/// Cloudflare applies before paging.
+fn list_rec(zone: &Zone, settings: &Settings, query: &[(&str, String)]) -> Result<Vec<Entry>> {
+    let query: Vec<(&str, &str)> = query.iter().map(|(k, v)| (*k, v.as_str())).collect();
+    api::paginate(
+        &zone.records_endpoint(),
+        &settings.token,
+        &query,
+        settings.per_page,
+    )
+    .context("Failed to list zone records")
+}
+
+/// Create a record and note it in the journal, returning it as Cloudflare
+/// has it, or nothing on a dry run.
+fn create_rec(zone: &Zone, body: &RecordBody, settings: &Settings) -> Result<Option<Entry>> {
+    let created = send_create(zone, body, settings)?;
+    if let Some(entry) = &created {
+        journal::append(zone, Action::Create, None, Some(entry), None);
+    }
+    Ok(created)
+}
+
+fn update_rec(
+    zone: &Zone,
+    entry: &Entry,
+    patch: &RecordPatch,
+    settings: &Settings,
+) -> Result<Option<Entry>> {
+    let updated = send_update(zone, entry, patch, settings)?;
+    if let Some(updated) = &updated {
+        journal::append(zone, Action::Update, Some(entry), Some(updated), None);
+    }
+    Ok(updated)
+}
+
+fn delete_rec(zone: &Zone, entry: &Entry, settings: &Settings) -> Result<bool> {
+    let deleted = send_delete(zone, entry, settings)?;
+    if deleted {
+        journal::append(zone, Action::Delete, Some(entry), None, None);
+    }
+    Ok(deleted)
+}
+
+fn send_create(zone: &Zone, body: &RecordBody, settings: &Settings) -> Result<Option<Entry>> {
+    let url = zone.records_endpoint();
+    let body = serde_json::to_value(body)?;
+    if settings.switches.dry_run {
+        api::print_request("POST", &url, Some(&body))?;
+        return Ok(None);
+    }
+    let name = body["name"].as_str().unwrap_or_default().to_owned();
+    let resp = api::call::<Entry>(
+        ureq::post(&url)
+            .set("Content-Type", "application/json")
+            .set("Authorization", &format!("Bearer {}", settings.token)),
+        Some(body),
+    )
+    .with_context(|| format!("Failed to create {}", name))?;
+    resp.result
+        .context("Cloudflare API returned no record")
+        .map(Some)
+}
+
+fn send_update(
+    zone: &Zone,
+    entry: &Entry,
+    patch: &RecordPatch,
+    settings: &Settings,
+) -> Result<Option<Entry>> {
+    let url = zone.record_endpoint(&entry.id);
+    let body = serde_json::to_value(patch)?;
+    if settings.switches.dry_run {
+        api::print_request("PATCH", &url, Some(&body))?;
+        return Ok(None);
+    }
+    let resp = api::call::<Entry>(
+        ureq::patch(&url)
+            .set("Content-Type", "application/json")
+            .set("Authorization", &format!("Bearer {}", settings.token)),
+        Some(body),
+    )
+    .with_context(|| format!("Failed to update {}", entry.name))?;
+    resp.result
+        .context("Cloudflare API returned no record")
+        .map(Some)
+}
+
+/// Delete a record, returning whether it was actually sent.
+fn send_delete(zone: &Zone, entry: &Entry, settings: &Settings) -> Result<bool> {
+    let url = zone.record_endpoint(&entry.id);
+    if settings.switches.dry_run {
+        api::print_request("DELETE", &url, None)?;
+        return Ok(false);
+    }
+    api::call::<serde_json::Value>(
+        ureq::delete(&url)
+            .set("Content-Type", "application/json")
+            .set("Authorization", &format!("Bearer {}", settings.token)),
+        None,
+    )
+    .with_context(|| format!("Failed to delete {}", entry.name))?;
+    Ok(true)
+}
+
+/// Work out which zone to manage: the one given by flag, environment or
+/// profile, or else whichever zone the record name falls in.
+fn resolve_zone(settings: &Settings, name: Option<&str>) -> Result<Zone> {
+    if let Some(zone) = &settings.zone {
+        return Zone::lookup(zone, &settings.token);
+    }
+
+    if let Some(name) = name.filter(|name| name.contains('.')) {
+        if let Some(zone) = Zone::infer(name, &settings.token)? {
+            return Ok(zone);
+        }
+    }
+
+    Err(Invalid(
+        "No zone given: pass --zone, set $CF_ZONE or $CF_ZONE_ID, add it to the config profile, or use a fully qualified record name"
+            .to_owned(),
+    )
+    .into())
+}
+
+fn run(conf: Config) -> Result<()> {
+    if let Subcommand::Show(s) = &conf.subcmd {
+        s.filter()?;
+        if let Some(columns) = &s.columns {
+            Column::parse_list(columns)?;
+        }
+    }
+    if let Subcommand::Set(s) = &conf.subcmd {
+        s.srv_labels()?;
+    }
+
+    let zone_required = match &conf.subcmd {
+        Subcommand::Show(s) => !s.all_zones,
+        // The zone can be inferred from a fully qualified name.
+        Subcommand::Set(s) => !s.name.contains('.'),
+        Subcommand::Del(s) => !s.name.contains('.'),
+        Subcommand::Export(_) | Subcommand::Import(_) | Subcommand::Backup(_) => true,
+        // The zone is in the snapshot, or in the journal.
+        Subcommand::Restore(_) | Subcommand::Undo(_) | Subcommand::History(_) => false,
+        // The zone can come from the desired-state file.
+        Subcommand::Plan(_) | Subcommand::Apply(_) => false,
+        Subcommand::Zones(_) | Subcommand::Config(_) | Subcommand::Token(_) => false,
+    };
+    let settings = Settings::load(
+        conf.config.as_deref(),
+        conf.profile.as_deref(),
+        conf.zone.as_deref(),
+        conf.per_page,
+        Switches {
+            dry_run: conf.dry_run,
+            yes: conf.yes,
+            max_delete: conf.max_delete,
+        },
+        Needs {
+            zone: zone_required,
+            // The journal is local, so no token is needed to read it.
+            token: !matches!(conf.subcmd, Subcommand::History(_)),
+        },
     )?;
 
-    let records = resp.result;
+    match &conf.subcmd {
+        Subcommand::Show(s) => {
+            let zones = if s.all_zones {
+                Zone::list(&settings.token)?
+            } else {
+                vec![resolve_zone(&settings, None)?]
+            };
+            let mut records = Vec::new();
+            for zone in zones {
+                let entries = list_rec(&zone, &settings, &s.query(&zone))?;
+                records.push((zone, entries));
+            }
+            show_rec(&records, s)
+        }
+        Subcommand::Set(s) => {
+            let zone = resolve_zone(&settings, Some(&s.name))?;
+            token::preflight(&settings.token, &zone)?;
+            set_rec(&zone, s, &settings)
+        }
+        Subcommand::Del(s) => {
+            let zone = resolve_zone(&settings, Some(&s.name))?;
+            token::preflight(&settings.token, &zone)?;
+            del_rec(&zone, s, &settings)
+        }
+        Subcommand::Export(s) => {
+            let zone = resolve_zone(&settings, None)?;
+            export_zone(&zone, s, &settings)
+        }
+        Subcommand::Import(s) => {
+            let zone = resolve_zone(&settings, None)?;
+            token::preflight(&settings.token, &zone)?;
+            import_zone(&zone, s, &settings)
+        }
+        Subcommand::Plan(s) => plan_zone(&Desired::load(&s.file)?, s, false, &settings),
+        Subcommand::Apply(s) => plan_zone(&Desired::load(&s.file)?, s, true, &settings),
+        Subcommand::Backup(s) => {
+            let zone = resolve_zone(&settings, None)?;
+            backup_zone(&zone, s, &settings)
+        }
+        Subcommand::Restore(s) => restore_zone(s, &settings),
+        Subcommand::History(s) => show_history(s, settings.zone.as_deref()),
+        Subcommand::Undo(s) => undo(s, &settings),
+        Subcommand::Zones(s) => list_zones(s, &settings),
+        Subcommand::Config(s) => check_config(s, &settings),
+        Subcommand::Token(s) => verify_token(s, &settings),
+    }
+}
+
+fn main() {
+    let conf = match Config::try_parse() {
+        Ok(conf) => conf,
+        Err(err)
+            if err.kind == ErrorKind::HelpDisplayed || err.kind == ErrorKind::VersionDisplayed =>
+        {
+            err.exit()
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(error::EXIT_INVALID);
+        }
+    };
 
-    match conf.subcmd {
-        Subcommand::Show(s) => show_rec(&records, &s.filter),
-        Subcommand::Set(s) => set_rec(&records, &s.name, &s.dest, &s.r#type),
-        Subcommand::Del(s) => del_rec(&records, &s.name),
+    if let Err(err) = run(conf) {
+        eprintln!("Error: {:?}", err);
+        process::exit(error::exit_code(&err));
     }
 }
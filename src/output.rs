@@ -0,0 +1,377 @@
+use std::env;
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde_json::Value;
+use tabwriter::TabWriter;
+
+use crate::error::Invalid;
+use crate::record::{Entry, TTL_AUTO};
+
+pub const FORMATS: &[&str] = &["table", "json", "jsonl", "csv", "yaml"];
+
+pub const COLUMNS: &[&str] = &[
+    "zone", "id", "type", "name", "content", "ttl", "proxied", "priority", "comment", "tags",
+    "created", "modified",
+];
+
+pub const DEFAULT_COLUMNS: &[&str] = &["type", "name", "content", "ttl", "proxied"];
+
+pub const SORT_KEYS: &[&str] = &["name", "type", "content", "modified"];
+
+/// Width of the table if `$COLUMNS` doesn't say otherwise.
+const DEFAULT_WIDTH: usize = 80;
+
+/// Content is never truncated to less than this, however narrow the table.
+const MIN_CONTENT_WIDTH: usize = 20;
+
+/// Space TabWriter puts between columns.
+const COLUMN_GAP: usize = 2;
+
+/// Fields of the machine readable formats, in CSV column order.
+const FIELDS: &[&str] = &[
+    "zone",
+    "id",
+    "name",
+    "type",
+    "content",
+    "ttl",
+    "proxied",
+    "proxiable",
+    "locked",
+    "priority",
+    "data",
+    "comment",
+    "tags",
+    "created_on",
+    "modified_on",
+];
+
+pub enum Format {
+    Table,
+    Json,
+    Jsonl,
+    Csv,
+    Yaml,
+}
+
+impl FromStr for Format {
+    type Err = Invalid;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "yaml" => Ok(Format::Yaml),
+            _ => Err(Invalid(format!(
+                "Unknown output format {}, expected one of: {}",
+                s,
+                FORMATS.join(", ")
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Column {
+    Zone,
+    Id,
+    Type,
+    Name,
+    Content,
+    Ttl,
+    Proxied,
+    Priority,
+    Comment,
+    Tags,
+    Created,
+    Modified,
+}
+
+impl FromStr for Column {
+    type Err = Invalid;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "zone" => Ok(Column::Zone),
+            "id" => Ok(Column::Id),
+            "type" => Ok(Column::Type),
+            "name" => Ok(Column::Name),
+            "content" => Ok(Column::Content),
+            "ttl" => Ok(Column::Ttl),
+            "proxied" => Ok(Column::Proxied),
+            "priority" => Ok(Column::Priority),
+            "comment" => Ok(Column::Comment),
+            "tags" => Ok(Column::Tags),
+            "created" => Ok(Column::Created),
+            "modified" => Ok(Column::Modified),
+            _ => Err(Invalid(format!(
+                "Unknown column {}, expected some of: {}",
+                s,
+                COLUMNS.join(", ")
+            ))),
+        }
+    }
+}
+
+impl Column {
+    /// Parse a comma separated list of columns.
+    pub fn parse_list(s: &str) -> Result<Vec<Column>, Invalid> {
+        s.split(',').map(str::parse).collect()
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            Column::Zone => "ZONE",
+            Column::Id => "ID",
+            Column::Type => "TYPE",
+            Column::Name => "NAME",
+            Column::Content => "CONTENT",
+            Column::Ttl => "TTL",
+            Column::Proxied => "PROXIED",
+            Column::Priority => "PRIORITY",
+            Column::Comment => "COMMENT",
+            Column::Tags => "TAGS",
+            Column::Created => "CREATED",
+            Column::Modified => "MODIFIED",
+        }
+    }
+
+    fn value(self, row: &Row) -> String {
+        let entry = row.entry;
+        match self {
+            Column::Zone => row.zone.to_owned(),
+            Column::Id => entry.id.clone(),
+            Column::Type => entry.r#type.clone(),
+            Column::Name => row.name.clone(),
+            Column::Content => entry.display_content(),
+            Column::Ttl => match entry.ttl {
+                TTL_AUTO => "auto".to_owned(),
+                ttl => ttl.to_string(),
+            },
+            Column::Proxied => if entry.proxied { "proxied" } else { "dns-only" }.to_owned(),
+            Column::Priority => entry.priority.map(|p| p.to_string()).unwrap_or_default(),
+            Column::Comment => entry.comment.clone().unwrap_or_default(),
+            Column::Tags => entry.tags.join(","),
+            Column::Created => entry.created_on.clone().unwrap_or_default(),
+            Column::Modified => entry.modified_on.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SortKey {
+    Name,
+    Type,
+    Content,
+    Modified,
+}
+
+impl FromStr for SortKey {
+    type Err = Invalid;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "type" => Ok(SortKey::Type),
+            "content" => Ok(SortKey::Content),
+            "modified" => Ok(SortKey::Modified),
+            _ => Err(Invalid(format!(
+                "Unknown sort key {}, expected one of: {}",
+                s,
+                SORT_KEYS.join(", ")
+            ))),
+        }
+    }
+}
+
+/// Sort rows by `key`, then by name and type so the order is stable.
+pub fn sort(rows: &mut [Row], key: SortKey, reverse: bool) {
+    rows.sort_by(|a, b| {
+        let (a, b) = if reverse { (b, a) } else { (a, b) };
+        let primary = match key {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Type => a.entry.r#type.cmp(&b.entry.r#type),
+            SortKey::Content => a.entry.display_content().cmp(&b.entry.display_content()),
+            SortKey::Modified => a.entry.modified_on.cmp(&b.entry.modified_on),
+        };
+        primary
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.entry.r#type.cmp(&b.entry.r#type))
+    });
+}
+
+/// How to lay out the table format.
+pub struct Table {
+    pub columns: Vec<Column>,
+    pub header: bool,
+    /// Truncate content to fit the terminal.
+    pub truncate: bool,
+}
+
+/// A record to show, along with the zone it belongs to and the name to show
+/// it under.
+pub struct Row<'a> {
+    pub zone: &'a str,
+    pub name: String,
+    pub entry: &'a Entry,
+}
+
+impl Row<'_> {
+    /// The record with its zone, as an object with every field of `FIELDS`.
+    fn to_value(&self) -> Result<Value> {
+        let mut value = serde_json::to_value(self.entry)?;
+        if let Value::Object(map) = &mut value {
+            map.insert("zone".to_owned(), Value::from(self.zone));
+            map.insert("name".to_owned(), Value::from(self.name.as_str()));
+        }
+        Ok(value)
+    }
+}
+
+pub fn write(out: &mut impl Write, format: &Format, rows: &[Row], table: &Table) -> Result<()> {
+    match format {
+        Format::Table => write_table(out, rows, table),
+        Format::Json => {
+            let values = rows.iter().map(Row::to_value).collect::<Result<Vec<_>>>()?;
+            serde_json::to_writer_pretty(&mut *out, &values)?;
+            writeln!(out)?;
+            Ok(())
+        }
+        Format::Jsonl => {
+            for row in rows {
+                serde_json::to_writer(&mut *out, &row.to_value()?)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        Format::Csv => write_csv(out, rows),
+        Format::Yaml => {
+            let values = rows.iter().map(Row::to_value).collect::<Result<Vec<_>>>()?;
+            serde_yaml_ng::to_writer(&mut *out, &values)?;
+            Ok(())
+        }
+    }
+}
+
+fn write_table(out: &mut impl Write, rows: &[Row], table: &Table) -> Result<()> {
+    let mut cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| table.columns.iter().map(|c| c.value(row)).collect())
+        .collect();
+    if table.header {
+        cells.insert(
+            0,
+            table
+                .columns
+                .iter()
+                .map(|c| c.header().to_owned())
+                .collect(),
+        );
+    }
+    if table.truncate {
+        truncate_content(&mut cells, &table.columns);
+    }
+
+    let mut tw = TabWriter::new(out);
+    for line in cells {
+        writeln!(&mut tw, "{}", line.join("\t"))?;
+    }
+    tw.flush()?;
+
+    Ok(())
+}
+
+/// Shorten content that would push the table past the terminal width, as
+/// long TXT and DKIM values otherwise make every line wrap.
+fn truncate_content(cells: &mut [Vec<String>], columns: &[Column]) {
+    let content = match columns.iter().position(|&c| c == Column::Content) {
+        Some(content) => content,
+        None => return,
+    };
+    let width = env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH);
+    let others: usize = (0..columns.len())
+        .filter(|&i| i != content)
+        .map(|i| {
+            let widest = cells.iter().map(|line| line[i].chars().count()).max();
+            widest.unwrap_or(0) + COLUMN_GAP
+        })
+        .sum();
+    let max = width.saturating_sub(others).max(MIN_CONTENT_WIDTH);
+
+    for line in cells {
+        let cell = &mut line[content];
+        if cell.chars().count() > max {
+            *cell = cell.chars().take(max - 3).chain("...".chars()).collect();
+        }
+    }
+}
+
+fn write_csv(out: &mut impl Write, rows: &[Row]) -> Result<()> {
+    writeln!(out, "{}", FIELDS.join(","))?;
+    for row in rows {
+        let value = row.to_value()?;
+        let cells: Vec<_> = FIELDS
+            .iter()
+            .map(|field| csv_cell(value.get(field).unwrap_or(&Value::Null)))
+            .collect();
+        writeln!(out, "{}", cells.join(","))?;
+    }
+    Ok(())
+}
+
+fn csv_cell(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        // Tags are joined with semicolons, to keep them in one cell.
+        Value::Array(items) if items.iter().all(Value::is_string) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(";"),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn yaml_reads_back_as_written() {
+        let entry: Entry = serde_json::from_value(json!({
+            "id": "1", "name": "yes.example.com", "type": "TXT", "content": "null: 1.0 # not a comment",
+            "comment": "on", "tags": ["env:prod", "123"]
+        }))
+        .unwrap();
+        let rows = vec![Row {
+            zone: "example.com",
+            name: "yes".to_owned(),
+            entry: &entry,
+        }];
+        let table = Table {
+            columns: Vec::new(),
+            header: true,
+            truncate: false,
+        };
+
+        let mut out = Vec::new();
+        write(&mut out, &Format::Yaml, &rows, &table).unwrap();
+        let read: Value = serde_yaml_ng::from_slice(&out).unwrap();
+        assert_eq!(read, Value::Array(vec![rows[0].to_value().unwrap()]));
+    }
+}
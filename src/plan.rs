@@ -0,0 +1,402 @@
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Settings;
+use crate::data;
+use crate::error::Invalid;
+use crate::record::{Entry, RecordBody, RecordPatch, Selector, TTL_AUTO};
+use crate::zone::Zone;
+use crate::zonefile;
+
+/// The records a zone should have, read from YAML or TOML:
+///
+/// ```yaml
+/// zone: example.com
+/// records:
+///   - name: www
+///     type: A
+///     content: 203.0.113.10
+///     proxied: true
+///   - name: "@"
+///     type: MX
+///     content: mail.example.com
+///     priority: 10
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Desired {
+    pub zone: Option<String>,
+    #[serde(default)]
+    pub records: Vec<DesiredRecord>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredRecord {
+    pub name: String,
+    pub r#type: String,
+    /// The content, or for structured types their data in presentation
+    /// format, e.g. `0 issue "letsencrypt.org"`.
+    pub content: Option<String>,
+    pub data: Option<Value>,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+    pub priority: Option<u16>,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Desired {
+    /// Read the file at `path`, as TOML if it ends in `.toml` and as YAML
+    /// otherwise.
+    pub fn load(path: &Path) -> Result<Desired> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let invalid = |err: String| Invalid(format!("Invalid {}: {}", path.display(), err));
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&text).map_err(|err| invalid(err.to_string()).into())
+        } else {
+            serde_yaml_ng::from_str(&text).map_err(|err| invalid(err.to_string()).into())
+        }
+    }
+
+    /// The records as they would be sent to Cloudflare, with names qualified
+    /// and defaults filled in from `settings`. Every problem is reported.
+    pub fn bodies(&self, zone: &Zone, settings: &Settings) -> Result<Vec<RecordBody>> {
+        let mut bodies = Vec::new();
+        let mut problems = Vec::new();
+        for (i, record) in self.records.iter().enumerate() {
+            match record.body(zone, settings) {
+                Ok(body) => bodies.push(body),
+                Err(err) => problems.push(format!("record {} ({}): {:#}", i + 1, record.name, err)),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(Invalid(format!(
+                "Invalid desired records:\n  - {}",
+                problems.join("\n  - ")
+            ))
+            .into());
+        }
+        Ok(bodies)
+    }
+}
+
+impl DesiredRecord {
+    fn body(&self, zone: &Zone, settings: &Settings) -> Result<RecordBody> {
+        let r#type = self.r#type.to_uppercase();
+        let mut body = RecordBody::new(&zone.qualify(&self.name), &r#type);
+        body.ttl = self.ttl.unwrap_or(settings.ttl);
+        body.proxied = self
+            .proxied
+            .unwrap_or_else(|| settings.proxied_for(&r#type));
+        if body.proxied {
+            body.ttl = TTL_AUTO;
+        }
+        body.priority = self.priority;
+        body.comment = self.comment.clone();
+        body.tags = self.tags.clone();
+
+        match (&self.data, &self.content) {
+            (Some(data), _) => body.data = Some(data.clone()),
+            (None, Some(content)) if data::is_structured(&r#type) => {
+                body.data = Some(Value::Object(data::parse(&r#type, content)?));
+            }
+            (None, Some(content)) => body.content = content.clone(),
+            (None, None) => return Err(Invalid("needs content or data".to_owned()).into()),
+        }
+        body.validate()?;
+        Ok(body)
+    }
+}
+
+pub enum Change<'a> {
+    Create(RecordBody),
+    Update(&'a Entry, RecordBody, RecordPatch),
+    Delete(&'a Entry),
+}
+
+/// Work out the changes that turn `live` into `desired`.
+///
+/// A name and type in the desired list is managed: its records are made to
+/// match the list exactly. A desired record updates the live record with
+/// the same content if there is one, or else any other live record of its
+/// name and type, so that changing an address is an update rather than a
+/// delete and a create. Records of unmanaged names and types are left
+/// alone, unless `prune`.
+pub fn diff<'a>(live: &'a [Entry], desired: Vec<RecordBody>, prune: bool) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+    let mut claimed = vec![false; live.len()];
+    let mut managed: Vec<(String, String)> = Vec::new();
+
+    // Pair each desired record with a live one of the same content.
+    let mut unpaired = Vec::new();
+    for body in desired {
+        let key = (body.name.to_lowercase(), body.r#type.clone());
+        if !managed.contains(&key) {
+            managed.push(key);
+        }
+        let found = live
+            .iter()
+            .enumerate()
+            .position(|(i, entry)| !claimed[i] && zonefile::same_record(entry, &body));
+        match found {
+            Some(i) => {
+                claimed[i] = true;
+                changes.push(update(&live[i], body));
+            }
+            None => unpaired.push(body),
+        }
+    }
+
+    // Then with any other live record of the same name and type.
+    for body in unpaired {
+        let selector = Selector {
+            name: &body.name,
+            r#type: Some(&body.r#type),
+            content: None,
+            id: None,
+        };
+        let found = live
+            .iter()
+            .enumerate()
+            .position(|(i, entry)| !claimed[i] && selector.matches(entry));
+        match found {
+            Some(i) => {
+                claimed[i] = true;
+                changes.push(update(&live[i], body));
+            }
+            None => changes.push(Change::Create(body)),
+        }
+    }
+
+    for (i, entry) in live.iter().enumerate() {
+        let key = (entry.name.to_lowercase(), entry.r#type.clone());
+        if !claimed[i] && (prune || managed.contains(&key)) {
+            changes.push(Change::Delete(entry));
+        }
+    }
+
+    changes.retain(|change| !matches!(change, Change::Update(_, _, patch) if patch.is_empty()));
+    changes
+}
+
+fn update(entry: &Entry, body: RecordBody) -> Change<'_> {
+    let patch = RecordPatch::between(entry, &body);
+    Change::Update(entry, body, patch)
+}
+
+/// Print the plan, in colour if stdout is a terminal.
+pub fn print(changes: &[Change]) -> Result<()> {
+    let color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for change in changes {
+        match change {
+            Change::Create(body) => writeln!(
+                out,
+                "{}",
+                paint(
+                    "32",
+                    format!(
+                        "  + {} {} {}",
+                        body.name,
+                        body.r#type,
+                        body.display_content()
+                    )
+                )
+            )?,
+            Change::Update(entry, body, patch) => {
+                writeln!(
+                    out,
+                    "{}",
+                    paint(
+                        "33",
+                        format!(
+                            "  ~ {} {} {}",
+                            entry.name,
+                            entry.r#type,
+                            entry.display_content()
+                        )
+                    )
+                )?;
+                for (field, old, new) in fields_changed(entry, body, patch) {
+                    writeln!(out, "      {}: {} -> {}", field, old, new)?;
+                }
+            }
+            Change::Delete(entry) => writeln!(
+                out,
+                "{}",
+                paint(
+                    "31",
+                    format!(
+                        "  - {} {} {}",
+                        entry.name,
+                        entry.r#type,
+                        entry.display_content()
+                    )
+                )
+            )?,
+        }
+    }
+
+    let count = |f: fn(&Change) -> bool| changes.iter().filter(|c| f(c)).count();
+    writeln!(
+        out,
+        "\nPlan: {} to create, {} to update, {} to delete.",
+        count(|c| matches!(c, Change::Create(_))),
+        count(|c| matches!(c, Change::Update(..))),
+        count(|c| matches!(c, Change::Delete(_))),
+    )?;
+    Ok(())
+}
+
+/// The fields a patch changes, as `(field, old, new)`.
+fn fields_changed(
+    entry: &Entry,
+    body: &RecordBody,
+    patch: &RecordPatch,
+) -> Vec<(&'static str, String, String)> {
+    let mut fields = Vec::new();
+    if patch.content.is_some() || patch.data.is_some() || patch.priority.is_some() {
+        fields.push(("content", entry.display_content(), body.display_content()));
+    }
+    if let Some(ttl) = patch.ttl {
+        fields.push(("ttl", entry.ttl.to_string(), ttl.to_string()));
+    }
+    if let Some(proxied) = patch.proxied {
+        fields.push(("proxied", entry.proxied.to_string(), proxied.to_string()));
+    }
+    if let Some(comment) = &patch.comment {
+        let old = entry.comment.clone().unwrap_or_default();
+        fields.push(("comment", format!("{:?}", old), format!("{:?}", comment)));
+    }
+    if let Some(tags) = &patch.tags {
+        fields.push(("tags", entry.tags.join(","), tags.join(",")));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn live() -> Vec<Entry> {
+        serde_json::from_value(json!([
+            {"id": "1", "name": "www.example.com", "type": "A", "content": "203.0.113.10"},
+            {"id": "2", "name": "www.example.com", "type": "A", "content": "203.0.113.11"},
+            {"id": "3", "name": "mail.example.com", "type": "A", "content": "203.0.113.20"},
+            {"id": "4", "name": "example.com", "type": "TXT", "content": "v=spf1 -all"},
+        ]))
+        .unwrap()
+    }
+
+    fn a(name: &str, content: &str) -> RecordBody {
+        let mut body = RecordBody::new(name, "A");
+        body.content = content.to_owned();
+        body
+    }
+
+    /// The changes as `+ name content`, `~ id content` and `- id`.
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Create(body) => format!("+ {} {}", body.name, body.content),
+                Change::Update(entry, body, _) => format!("~ {} {}", entry.id, body.content),
+                Change::Delete(entry) => format!("- {}", entry.id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_records_need_nothing() {
+        let live = live();
+        let desired = vec![
+            a("www.example.com", "203.0.113.11"),
+            a("www.example.com", "203.0.113.10"),
+        ];
+        assert!(diff(&live, desired, false).is_empty());
+    }
+
+    #[test]
+    fn same_content_is_paired_before_name_and_type() {
+        let live = live();
+        // .11 pairs with record 2 even though record 1 comes first, leaving
+        // record 1 to be updated to .12.
+        let desired = vec![
+            a("www.example.com", "203.0.113.12"),
+            a("www.example.com", "203.0.113.11"),
+        ];
+        assert_eq!(
+            summary(&diff(&live, desired, false)),
+            vec!["~ 1 203.0.113.12"]
+        );
+    }
+
+    #[test]
+    fn changes_that_only_touch_other_fields_are_updates() {
+        let live = live();
+        let mut body = a("mail.example.com", "203.0.113.20");
+        body.ttl = 300;
+        let changes = diff(&live, vec![body], false);
+        assert_eq!(summary(&changes), vec!["~ 3 203.0.113.20"]);
+        match &changes[0] {
+            Change::Update(_, _, patch) => assert_eq!(patch.ttl, Some(300)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn managed_names_are_made_to_match() {
+        let live = live();
+        let desired = vec![
+            a("www.example.com", "203.0.113.10"),
+            a("new.example.com", "203.0.113.30"),
+        ];
+        assert_eq!(
+            summary(&diff(&live, desired, false)),
+            vec!["+ new.example.com 203.0.113.30", "- 2"]
+        );
+    }
+
+    #[test]
+    fn unmanaged_records_are_only_deleted_with_prune() {
+        let live = live();
+        let desired = || vec![a("www.example.com", "203.0.113.10")];
+        assert_eq!(summary(&diff(&live, desired(), false)), vec!["- 2"]);
+        assert_eq!(
+            summary(&diff(&live, desired(), true)),
+            vec!["- 2", "- 3", "- 4"]
+        );
+    }
+
+    #[test]
+    fn other_types_of_a_managed_name_are_unmanaged() {
+        let live = live();
+        let mut txt = RecordBody::new("example.com", "TXT");
+        txt.content = "v=spf1 mx -all".to_owned();
+        let desired = vec![txt, a("example.com", "203.0.113.1")];
+        assert_eq!(
+            summary(&diff(&live, desired, false)),
+            vec!["~ 4 v=spf1 mx -all", "+ example.com 203.0.113.1"]
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::data;
+use crate::error::Invalid;
+
+pub const RECORD_TYPES: &[&str] = &[
+    "A", "AAAA", "CAA", "CERT", "CNAME", "DNSKEY", "DS", "HTTPS", "LOC", "MX", "NAPTR", "NS",
+    "PTR", "SMIMEA", "SRV", "SSHFP", "SVCB", "TLSA", "TXT", "URI",
+];
+
+/// Types Cloudflare can put behind its proxy.
+pub const PROXIABLE_TYPES: &[&str] = &["A", "AAAA", "CNAME"];
+
+/// Types that carry a priority alongside their content.
+pub const PRIORITY_TYPES: &[&str] = &["MX", "URI"];
+
+/// A TTL of 1 tells Cloudflare to pick the TTL automatically.
+pub const TTL_AUTO: u32 = 1;
+
+/// A DNS record as returned by the API.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Entry {
+    pub id: String,
+    pub name: String,
+    pub r#type: String,
+    pub content: String,
+    #[serde(default = "ttl_auto")]
+    pub ttl: u32,
+    #[serde(default)]
+    pub proxied: bool,
+    #[serde(default)]
+    pub proxiable: bool,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default)]
+    pub priority: Option<u16>,
+    #[serde(default)]
+    pub data: Option<Value>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub created_on: Option<String>,
+    #[serde(default)]
+    pub modified_on: Option<String>,
+}
+
+fn ttl_auto() -> u32 {
+    TTL_AUTO
+}
+
+impl Entry {
+    /// The record's value, rendered from its data where it has any.
+    pub fn display_content(&self) -> String {
+        display_content(&self.r#type, &self.content, self.priority, &self.data)
+    }
+}
+
+fn display_content(
+    r#type: &str,
+    content: &str,
+    priority: Option<u16>,
+    data: &Option<Value>,
+) -> String {
+    let value = match data {
+        Some(data) if data::is_structured(r#type) => data::render(r#type, data),
+        _ => content.to_owned(),
+    };
+    match priority {
+        Some(priority) if PRIORITY_TYPES.contains(&r#type) => format!("{} {}", priority, value),
+        _ => value,
+    }
+}
+
+/// Picks out records by name and, optionally, type, content and ID.
+pub struct Selector<'a> {
+    pub name: &'a str,
+    pub r#type: Option<&'a str>,
+    pub content: Option<&'a str>,
+    pub id: Option<&'a str>,
+}
+
+impl Selector<'_> {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        entry.name.eq_ignore_ascii_case(self.name)
+            && self.r#type.is_none_or(|t| entry.r#type == t)
+            && self
+                .content
+                .is_none_or(|c| entry.content == c || entry.display_content() == c)
+            && self.id.is_none_or(|id| entry.id == id)
+    }
+
+    /// Query parameters that have Cloudflare return only the records with
+    /// this name and type. Content isn't sent, as it may be given in the
+    /// displayed form, which the API doesn't know about.
+    pub fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![("name", self.name.to_lowercase())];
+        if let Some(r#type) = self.r#type {
+            query.push(("type", r#type.to_owned()));
+        }
+        query.push(("match", "all".to_owned()));
+        query
+    }
+}
+
+/// The writable subset of a record, as sent on create and update.
+#[derive(Serialize)]
+pub struct RecordBody {
+    pub name: String,
+    pub r#type: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub content: String,
+    pub ttl: u32,
+    pub proxied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl RecordBody {
+    pub fn new(name: &str, r#type: &str) -> Self {
+        RecordBody {
+            name: name.to_owned(),
+            r#type: r#type.to_owned(),
+            content: String::new(),
+            ttl: TTL_AUTO,
+            proxied: false,
+            priority: None,
+            data: None,
+            comment: None,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn display_content(&self) -> String {
+        display_content(&self.r#type, &self.content, self.priority, &self.data)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        validate_type(&self.r#type)?;
+        if data::is_structured(&self.r#type) {
+            match &self.data {
+                Some(Value::Object(data)) => data::check(&self.r#type, data)?,
+                _ => {
+                    return Err(
+                        Invalid(format!("{} records need structured data", self.r#type)).into(),
+                    )
+                }
+            }
+        } else {
+            validate_content(&self.r#type, &self.content)?;
+        }
+        validate_ttl(self.ttl)?;
+        if self.proxied && !PROXIABLE_TYPES.contains(&self.r#type.as_str()) {
+            return Err(Invalid(format!("{} records cannot be proxied", self.r#type)).into());
+        }
+        if self.priority.is_none() && PRIORITY_TYPES.contains(&self.r#type.as_str()) {
+            return Err(Invalid(format!("{} records need a --priority", self.r#type)).into());
+        }
+        Ok(())
+    }
+}
+
+/// The fields of a record that differ from what Cloudflare has, as sent on
+/// a partial update.
+#[derive(Serialize, Default)]
+pub struct RecordPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+impl RecordPatch {
+    /// Collect the fields of `new` that differ from `old`.
+    pub fn between(old: &Entry, new: &RecordBody) -> Self {
+        fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+            if old == new {
+                None
+            } else {
+                Some(new.clone())
+            }
+        }
+
+        RecordPatch {
+            // Cloudflare derives the content of structured records from data.
+            content: if data::is_structured(&new.r#type) {
+                None
+            } else {
+                changed(&old.content, &new.content)
+            },
+            ttl: changed(&old.ttl, &new.ttl),
+            proxied: changed(&old.proxied, &new.proxied),
+            priority: changed(&old.priority, &new.priority).flatten(),
+            data: changed(&old.data, &new.data).flatten(),
+            comment: changed(&old.comment, &new.comment).map(|c| c.unwrap_or_default()),
+            tags: changed(&old.tags, &new.tags),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_none()
+            && self.ttl.is_none()
+            && self.proxied.is_none()
+            && self.priority.is_none()
+            && self.data.is_none()
+            && self.comment.is_none()
+            && self.tags.is_none()
+    }
+}
+
+impl From<&Entry> for RecordBody {
+    fn from(entry: &Entry) -> Self {
+        RecordBody {
+            name: entry.name.clone(),
+            r#type: entry.r#type.clone(),
+            content: if data::is_structured(&entry.r#type) {
+                String::new()
+            } else {
+                entry.content.clone()
+            },
+            ttl: entry.ttl,
+            proxied: entry.proxied,
+            priority: entry.priority,
+            data: entry.data.clone(),
+            comment: entry.comment.clone(),
+            tags: entry.tags.clone(),
+        }
+    }
+}
+
+pub fn validate_type(r#type: &str) -> Result<()> {
+    if !RECORD_TYPES.contains(&r#type) {
+        return Err(Invalid(format!("Unknown DNS record type: {}", r#type)).into());
+    }
+    Ok(())
+}
+
+pub fn validate_content(r#type: &str, content: &str) -> Result<()> {
+    let valid = match r#type {
+        "A" => content.parse::<Ipv4Addr>().is_ok(),
+        "AAAA" => content.parse::<Ipv6Addr>().is_ok(),
+        _ => !content.is_empty(),
+    };
+    if !valid {
+        return Err(Invalid(format!(
+            "{} is not valid content for a {} record",
+            content, r#type
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+pub fn validate_ttl(ttl: u32) -> Result<()> {
+    if ttl != TTL_AUTO && !(30..=86400).contains(&ttl) {
+        return Err(Invalid(format!(
+            "TTL must be 1 (automatic) or between 30 and 86400, got {}",
+            ttl
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn entry(value: Value) -> Entry {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn patch_of_unchanged_record_is_empty() {
+        let entry = entry(json!({
+            "id": "1", "name": "www.example.com", "type": "A", "content": "203.0.113.10",
+            "ttl": 300, "comment": "web", "tags": ["env:prod"]
+        }));
+        assert!(RecordPatch::between(&entry, &RecordBody::from(&entry)).is_empty());
+    }
+
+    #[test]
+    fn patch_holds_only_changes() {
+        let entry = entry(json!({
+            "id": "1", "name": "www.example.com", "type": "A", "content": "203.0.113.10",
+            "ttl": 300, "comment": "web"
+        }));
+        let mut body = RecordBody::from(&entry);
+        body.content = "203.0.113.11".to_owned();
+        body.proxied = true;
+        body.comment = None;
+        let patch = RecordPatch::between(&entry, &body);
+        assert_eq!(
+            serde_json::to_value(&patch).unwrap(),
+            json!({"content": "203.0.113.11", "proxied": true, "comment": ""})
+        );
+    }
+
+    #[test]
+    fn structured_records_patch_data_not_content() {
+        let entry = entry(json!({
+            "id": "1", "name": "example.com", "type": "CAA", "content": "0 issue \"letsencrypt.org\"",
+            "data": {"flags": 0, "tag": "issue", "value": "letsencrypt.org"}
+        }));
+        let mut body = RecordBody::from(&entry);
+        body.data = Some(json!({"flags": 0, "tag": "issue", "value": "pki.goog"}));
+        let patch = RecordPatch::between(&entry, &body);
+        assert_eq!(patch.content, None);
+        assert_eq!(patch.data, body.data);
+    }
+
+    #[test]
+    fn selector_ignores_name_case() {
+        let entry = entry(
+            json!({"id": "1", "name": "www.example.com", "type": "MX", "content": "mail.example.com", "priority": 10}),
+        );
+        let selector = Selector {
+            name: "WWW.example.com",
+            r#type: Some("MX"),
+            content: Some("10 mail.example.com"),
+            id: None,
+        };
+        assert!(selector.matches(&entry));
+    }
+}
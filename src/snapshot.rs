@@ -0,0 +1,191 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error::{Invalid, NotFound};
+use crate::record::Entry;
+use crate::time;
+use crate::zone::Zone;
+
+/// Every record of a zone at one point in time.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub zone: Zone,
+    pub taken_at: String,
+    pub records: Vec<Entry>,
+}
+
+/// Where snapshots of `zone` are kept: a directory per zone, under `base`
+/// or else `$XDG_DATA_HOME/cf-record/backups`.
+pub fn dir(base: Option<&Path>, zone: &str) -> Result<PathBuf> {
+    let base = match base {
+        Some(base) => base.to_owned(),
+        None => config::data_dir()
+            .map(|dir| dir.join("backups"))
+            .context("Cannot find a backup directory: set $HOME or pass --dir")?,
+    };
+    Ok(base.join(zone.to_lowercase()))
+}
+
+/// Write a snapshot of `records` into `dir`, named after the time it was
+/// taken, and return its path. Snapshots taken the same second get a `-2`,
+/// `-3`... suffix rather than overwriting one another.
+pub fn save(dir: &Path, zone: &Zone, records: Vec<Entry>) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create backup directory {}", dir.display()))?;
+    let now = time::now();
+    let snapshot = Snapshot {
+        zone: zone.clone(),
+        taken_at: time::rfc3339(now),
+        records,
+    };
+
+    let stem = time::compact(now);
+    let mut n = 1;
+    let (mut file, path) = loop {
+        let path = match n {
+            1 => dir.join(format!("{}.json", stem)),
+            n => dir.join(format!("{}-{}.json", stem, n)),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => break (file, path),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to write {}", path.display()))
+            }
+        }
+    };
+    serde_json::to_writer_pretty(&mut file, &snapshot)?;
+    writeln!(file)?;
+    Ok(path)
+}
+
+pub fn load(path: &Path) -> Result<Snapshot> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+    serde_json::from_str(&text)
+        .map_err(|err| Invalid(format!("Invalid snapshot {}: {}", path.display(), err)).into())
+}
+
+/// The snapshots in `dir`, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort_by_cached_key(|path| order(path));
+    Ok(paths)
+}
+
+/// Sort key of a snapshot: its time, then its suffix, so that `-10` comes
+/// after `-9`.
+fn order(path: &Path) -> (String, u32) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match stem.split_once('-') {
+        Some((time, n)) => match n.parse() {
+            Ok(n) => (time.to_owned(), n),
+            Err(_) => (stem, 0),
+        },
+        None => (stem, 1),
+    }
+}
+
+/// Delete all but the newest `keep` snapshots in `dir`, returning those
+/// that were deleted.
+pub fn prune(dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    let paths = list(dir)?;
+    let old = paths.len().saturating_sub(keep);
+    let mut removed = Vec::new();
+    for path in paths.into_iter().take(old) {
+        fs::remove_file(&path).with_context(|| format!("Failed to delete {}", path.display()))?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Find the snapshot the user named: a path, a file name in `dir` (with or
+/// without `.json`), or `latest`.
+pub fn find(name: &str, dir: Option<&Path>) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path.is_file() {
+        return Ok(path.to_owned());
+    }
+    let dir = dir.ok_or_else(|| {
+        NotFound(format!(
+            "No snapshot file {}; pass a path, or --zone to look in the zone's backups",
+            name
+        ))
+    })?;
+    let found = if name == "latest" {
+        list(dir)?.pop()
+    } else {
+        vec![dir.join(name), dir.join(format!("{}.json", name))]
+            .into_iter()
+            .find(|path| path.is_file())
+    };
+    found.ok_or_else(|| NotFound(format!("No snapshot {} in {}", name, dir.display())).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn snapshots_of_the_same_second_are_kept_in_order() {
+        let dir = TestDir::new("snapshots");
+        let zone: Zone = serde_json::from_value(json!({"id": "1", "name": "example.com"})).unwrap();
+
+        let saved: Vec<_> = (0..11)
+            .map(|_| save(&dir, &zone, Vec::new()).unwrap())
+            .collect();
+        assert_eq!(list(&dir).unwrap(), saved);
+        assert_eq!(find("latest", Some(&dir)).unwrap(), saved[10]);
+
+        let removed = prune(&dir, 1).unwrap();
+        assert_eq!(removed, saved[..10]);
+        assert_eq!(list(&dir).unwrap(), saved[10..]);
+    }
+
+    #[test]
+    fn suffixes_sort_numerically() {
+        let names = [
+            "20240501T123000Z-10",
+            "20240501T123000Z-2",
+            "20240501T123000Z",
+            "20240501T122959Z",
+        ];
+        let mut paths: Vec<_> = names
+            .iter()
+            .map(|n| PathBuf::from(format!("{}.json", n)))
+            .collect();
+        paths.sort_by_cached_key(|path| order(path));
+        let sorted: Vec<_> = paths
+            .iter()
+            .map(|p| p.file_stem().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            sorted,
+            vec![
+                "20240501T122959Z",
+                "20240501T123000Z",
+                "20240501T123000Z-2",
+                "20240501T123000Z-10"
+            ]
+        );
+    }
+}
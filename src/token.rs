@@ -0,0 +1,428 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::api::{self, ApiError};
+use crate::config::{self, Profile};
+use crate::error::{Invalid, Unauthorized};
+use crate::time::now;
+use crate::zone::Zone;
+
+/// Name of the systemd credential holding the token, as in
+/// `LoadCredential=cf-zone-token:/etc/cf-record/token`.
+pub const CREDENTIAL_NAME: &str = "cf-zone-token";
+
+pub struct Token {
+    pub secret: String,
+    /// Where the token was found, for diagnostics.
+    pub source: String,
+}
+
+impl Token {
+    fn new(secret: String, source: String) -> Self {
+        Token { secret, source }
+    }
+}
+
+/// Find the API token, trying in order: `$CF_ZONE_TOKEN`, the file named by
+/// `$CF_ZONE_TOKEN_FILE`, the systemd credential, and then the profile's
+/// `token`, `token_file` and `token_command`.
+pub fn load(profile: &Profile) -> Result<Option<Token>> {
+    if let Ok(token) = env::var("CF_ZONE_TOKEN") {
+        return Ok(Some(Token::new(token, "$CF_ZONE_TOKEN".to_owned())));
+    }
+    if let Some(path) = env::var_os("CF_ZONE_TOKEN_FILE") {
+        let path = Path::new(&path);
+        let source = format!("$CF_ZONE_TOKEN_FILE ({})", path.display());
+        return read_file(path).map(|token| Some(Token::new(token, source)));
+    }
+    if let Some(dir) = env::var_os("CREDENTIALS_DIRECTORY") {
+        let path = PathBuf::from(dir).join(CREDENTIAL_NAME);
+        if path.exists() {
+            let source = format!("systemd credential ({})", path.display());
+            return read_file(&path).map(|token| Some(Token::new(token, source)));
+        }
+    }
+    if let Some(token) = &profile.token {
+        return Ok(Some(Token::new(token.clone(), "profile token".to_owned())));
+    }
+    if let Some(path) = &profile.token_file {
+        let source = format!("profile token_file ({})", path.display());
+        return read_file(path).map(|token| Some(Token::new(token, source)));
+    }
+    if let Some(command) = &profile.token_command {
+        let source = format!("profile token_command ({})", command);
+        return run_command(command).map(|token| Some(Token::new(token, source)));
+    }
+    Ok(None)
+}
+
+/// How long a token's permissions are trusted before checking again.
+const CACHE_SECONDS: u64 = 3600;
+const CACHE_FILE: &str = "tokens.json";
+
+/// Permission groups that let a token read and edit DNS records.
+const DNS_READ: &str = "DNS Read";
+const DNS_WRITE: &str = "DNS Write";
+
+/// What Cloudflare knows about a token.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TokenStatus {
+    pub id: String,
+    pub status: String,
+    #[serde(default)]
+    pub not_before: Option<String>,
+    #[serde(default)]
+    pub expires_on: Option<String>,
+}
+
+/// Ask Cloudflare whether the token is valid, failing if it is not active.
+pub fn verify(token: &str) -> Result<TokenStatus> {
+    let resp = api::call::<TokenStatus>(
+        ureq::get(&format!("{}/user/tokens/verify", api::BASE))
+            .set("Content-Type", "application/json")
+            .set("Authorization", &format!("Bearer {}", token)),
+        None,
+    )
+    .context("Failed to verify token")?;
+    let status = resp
+        .result
+        .context("Cloudflare API returned no token status")?;
+    if status.status != "active" {
+        return Err(Unauthorized(format!("Token {} is {}", status.id, status.status)).into());
+    }
+    Ok(status)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Policy {
+    pub effect: String,
+    #[serde(default)]
+    pub resources: Map<String, Value>,
+    #[serde(default)]
+    pub permission_groups: Vec<PermissionGroup>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PermissionGroup {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct TokenDetails {
+    #[serde(default)]
+    policies: Vec<Policy>,
+}
+
+/// A token's status along with its policies, which are only available if
+/// the token may read its own details.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TokenInfo {
+    pub status: TokenStatus,
+    pub policies: Option<Vec<Policy>>,
+}
+
+impl TokenInfo {
+    pub fn fetch(token: &str) -> Result<TokenInfo> {
+        let status = verify(token)?;
+        let resp = api::call::<TokenDetails>(
+            ureq::get(&format!("{}/user/tokens/{}", api::BASE, status.id))
+                .set("Content-Type", "application/json")
+                .set("Authorization", &format!("Bearer {}", token)),
+            None,
+        );
+        let policies = match resp {
+            Ok(resp) => resp.result.map(|details| details.policies),
+            Err(err)
+                if err
+                    .downcast_ref::<ApiError>()
+                    .is_some_and(ApiError::is_auth) =>
+            {
+                None
+            }
+            Err(err) => return Err(err).context("Failed to read token permissions"),
+        };
+        Ok(TokenInfo { status, policies })
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Access {
+    Write,
+    Read,
+    Nothing,
+}
+
+/// The DNS access that `policies` grant on `zone`, with deny policies taking
+/// precedence over allow policies.
+pub fn zone_access(policies: &[Policy], zone: &Zone) -> Access {
+    let granted = |effect: &str, group: &str| {
+        policies.iter().any(|policy| {
+            policy.effect == effect
+                && policy.permission_groups.iter().any(|g| g.name == group)
+                && covers(&policy.resources, zone)
+        })
+    };
+    let has = |group| granted("allow", group) && !granted("deny", group);
+
+    if has(DNS_WRITE) {
+        Access::Write
+    } else if has(DNS_READ) {
+        Access::Read
+    } else {
+        Access::Nothing
+    }
+}
+
+/// Whether a policy's resources include `zone`, either directly, through a
+/// wildcard, or through the account that owns it.
+fn covers(resources: &Map<String, Value>, zone: &Zone) -> bool {
+    let zone_key = format!("com.cloudflare.api.account.zone.{}", zone.id);
+    let account_key = format!("com.cloudflare.api.account.{}", zone.account.id);
+
+    resources.iter().any(|(key, value)| {
+        let is_zone = *key == zone_key || key == "com.cloudflare.api.account.zone.*";
+        let is_account = *key == account_key || key == "com.cloudflare.api.account.*";
+        match value {
+            Value::String(_) => is_zone || is_account,
+            Value::Object(children) => is_account && covers(children, zone),
+            _ => false,
+        }
+    })
+}
+
+/// Check, before changing anything, that the token may edit DNS records of
+/// `zone`. The answer is cached for a while, and if the token cannot read
+/// its own permissions the check is skipped.
+pub fn preflight(token: &str, zone: &Zone) -> Result<()> {
+    let info = cached_info(token)?;
+    let policies = match &info.policies {
+        Some(policies) => policies,
+        None => return Ok(()),
+    };
+    match zone_access(policies, zone) {
+        Access::Write => Ok(()),
+        // Without the owning account, account-wide grants can't be ruled out.
+        _ if zone.account.id.is_empty() => Ok(()),
+        Access::Read => {
+            Err(Unauthorized(format!("Token is read-only for zone {}", zone.name)).into())
+        }
+        Access::Nothing => {
+            Err(Unauthorized(format!("Token has no DNS access to zone {}", zone.name)).into())
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    key: String,
+    checked_at: u64,
+    info: TokenInfo,
+}
+
+fn cached_info(token: &str) -> Result<TokenInfo> {
+    let key = cache_key(token);
+    let fresh = config::load_cache::<Vec<CacheEntry>>(CACHE_FILE)
+        .into_iter()
+        .find(|e| e.key == key && now().saturating_sub(e.checked_at) < CACHE_SECONDS);
+    match fresh {
+        Some(entry) => Ok(entry.info),
+        None => refresh(token),
+    }
+}
+
+/// Fetch the token's status and permissions, and remember them.
+pub fn refresh(token: &str) -> Result<TokenInfo> {
+    let info = TokenInfo::fetch(token)?;
+    let key = cache_key(token);
+    let mut entries = config::load_cache::<Vec<CacheEntry>>(CACHE_FILE);
+    entries.retain(|e| e.key != key);
+    entries.push(CacheEntry {
+        key,
+        checked_at: now(),
+        info: info.clone(),
+    });
+    config::save_cache(CACHE_FILE, &entries);
+    Ok(info)
+}
+
+/// Tokens are cached under a hash, never in the clear.
+pub fn cache_key(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    warn_if_world_readable(path);
+    let token = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read token file {}", path.display()))?;
+    non_empty(token.trim(), &path.display().to_string())
+}
+
+fn run_command(command: &str) -> Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .with_context(|| format!("Failed to run token command: {}", command))?;
+    if !output.status.success() {
+        return Err(Invalid(format!(
+            "Token command `{}` failed ({}): {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    // Like `pass`, the token is taken to be the first line of the output.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    non_empty(stdout.lines().next().unwrap_or("").trim(), command)
+}
+
+fn non_empty(token: &str, source: &str) -> Result<String> {
+    if token.is_empty() {
+        return Err(Invalid(format!("Token from {} is empty", source)).into());
+    }
+    Ok(token.to_owned())
+}
+
+/// Files holding secrets should not be readable by every user.
+#[cfg(unix)]
+pub fn warn_if_world_readable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(meta) = fs::metadata(path) {
+        if meta.permissions().mode() & 0o004 != 0 {
+            eprintln!(
+                "Warning: {} is world-readable, consider `chmod 600 {}`",
+                path.display(),
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn warn_if_world_readable(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn zone() -> Zone {
+        serde_json::from_value(json!({
+            "id": "z1", "name": "example.com", "account": {"id": "a1"}
+        }))
+        .unwrap()
+    }
+
+    fn policy(effect: &str, group: &str, resources: Value) -> Policy {
+        serde_json::from_value(json!({
+            "effect": effect,
+            "resources": resources,
+            "permission_groups": [{"name": group}]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn deny_beats_allow() {
+        let zone = zone();
+        let allow = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.zone.z1": "*"}),
+        );
+        assert_eq!(
+            zone_access(std::slice::from_ref(&allow), &zone),
+            Access::Write
+        );
+
+        let deny = policy(
+            "deny",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.zone.*": "*"}),
+        );
+        assert_eq!(zone_access(&[allow.clone(), deny], &zone), Access::Nothing);
+
+        let read = policy(
+            "allow",
+            DNS_READ,
+            json!({"com.cloudflare.api.account.zone.z1": "*"}),
+        );
+        let deny = policy(
+            "deny",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.zone.z1": "*"}),
+        );
+        assert_eq!(zone_access(&[allow, read, deny], &zone), Access::Read);
+    }
+
+    #[test]
+    fn zone_keys_match_by_id_or_wildcard() {
+        let zone = zone();
+        let other = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.zone.z2": "*"}),
+        );
+        assert_eq!(zone_access(&[other], &zone), Access::Nothing);
+
+        let any = policy(
+            "allow",
+            DNS_READ,
+            json!({"com.cloudflare.api.account.zone.*": "*"}),
+        );
+        assert_eq!(zone_access(&[any], &zone), Access::Read);
+    }
+
+    #[test]
+    fn account_resources_cover_their_zones() {
+        let zone = zone();
+        let account = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.a1": "*"}),
+        );
+        assert_eq!(zone_access(&[account], &zone), Access::Write);
+
+        let nested = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.*": {"com.cloudflare.api.account.zone.z1": "*"}}),
+        );
+        assert_eq!(zone_access(&[nested], &zone), Access::Write);
+
+        let nested_other = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.a1": {"com.cloudflare.api.account.zone.z2": "*"}}),
+        );
+        assert_eq!(zone_access(&[nested_other], &zone), Access::Nothing);
+
+        // Only accounts hold zones, so a zone key can't nest further.
+        let nested_zone = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.zone.z1": {"com.cloudflare.api.account.zone.z1": "*"}}),
+        );
+        assert_eq!(zone_access(&[nested_zone], &zone), Access::Nothing);
+
+        let other_account = policy(
+            "allow",
+            DNS_WRITE,
+            json!({"com.cloudflare.api.account.a2": "*"}),
+        );
+        assert_eq!(zone_access(&[other_account], &zone), Access::Nothing);
+    }
+}
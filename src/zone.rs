@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::api;
+use crate::config;
+use crate::error::NotFound;
+use crate::time::now;
+use crate::token;
+
+/// A zone as returned by the API.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Zone {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub plan: Plan,
+    #[serde(default)]
+    pub name_servers: Vec<String>,
+    #[serde(default)]
+    pub account: Account,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Account {
+    pub id: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Plan {
+    pub name: String,
+}
+
+impl Zone {
+    pub fn endpoint(&self) -> String {
+        format!("{}/zones/{}", api::BASE, self.id)
+    }
+
+    pub fn records_endpoint(&self) -> String {
+        format!("{}/dns_records", self.endpoint())
+    }
+
+    pub fn record_endpoint(&self, id: &str) -> String {
+        format!("{}/{}", self.records_endpoint(), id)
+    }
+
+    pub fn fetch(id: &str, token: &str) -> Result<Zone> {
+        let resp = api::call::<Zone>(
+            ureq::get(&format!("{}/zones/{}", api::BASE, id))
+                .set("Content-Type", "application/json")
+                .set("Authorization", &format!("Bearer {}", token)),
+            None,
+        )
+        .context("Failed to look up zone")?;
+        resp.result.context("Cloudflare API returned no zone")
+    }
+
+    /// Find a zone given either its ID or its domain name.
+    pub fn lookup(zone: &str, token: &str) -> Result<Zone> {
+        if is_zone_id(zone) {
+            return Zone::fetch(zone, token);
+        }
+
+        let name = zone.trim_end_matches('.').to_lowercase();
+        if let Some(zones) = fresh_listing(token) {
+            if let Some(zone) = zones.into_iter().find(|z| z.name == name) {
+                return Ok(zone);
+            }
+        }
+
+        let found: Vec<Zone> = api::paginate(
+            &format!("{}/zones", api::BASE),
+            token,
+            &[("name", &name)],
+            50,
+        )
+        .context("Failed to look up zone")?;
+        found.into_iter().next().ok_or_else(|| {
+            NotFound(format!("No zone named {} is visible to this token", name)).into()
+        })
+    }
+
+    /// Find the zone a fully qualified record name belongs to, by longest
+    /// suffix match against the zones the token can see. A cached listing is
+    /// only used while fresh, as a zone delegated since would be missed and
+    /// the record created in its parent.
+    pub fn infer(record: &str, token: &str) -> Result<Option<Zone>> {
+        let record = record.trim_end_matches('.').to_lowercase();
+        let zones = match fresh_listing(token) {
+            Some(zones) => zones,
+            None => Zone::list(token)?,
+        };
+        Ok(longest_match(&zones, &record))
+    }
+
+    /// Every zone the token can see, remembered for `infer` and `lookup`.
+    pub fn list(token: &str) -> Result<Vec<Zone>> {
+        let zones: Vec<Zone> = api::paginate(&format!("{}/zones", api::BASE), token, &[], 50)
+            .context("Failed to list zones")?;
+        save_listing(token, &zones);
+        Ok(zones)
+    }
+
+    /// Turn a record name as typed by the user into the fully qualified form
+    /// Cloudflare uses: `@` is the apex, names outside the zone are taken to
+    /// be relative to it, and case and trailing dots are dropped.
+    pub fn qualify(&self, name: &str) -> String {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let zone = self.name.to_lowercase();
+        if name == "@" || name.is_empty() {
+            zone
+        } else if self.contains(&name) {
+            name
+        } else {
+            format!("{}.{}", name.trim_end_matches(".@"), zone)
+        }
+    }
+
+    /// The inverse of `qualify`, with `@` for the apex.
+    pub fn relative(&self, name: &str) -> String {
+        if name.eq_ignore_ascii_case(&self.name) {
+            return "@".to_owned();
+        }
+        let suffix = format!(".{}", self.name);
+        match name.len().checked_sub(suffix.len()) {
+            Some(at) if name.is_char_boundary(at) && name[at..].eq_ignore_ascii_case(&suffix) => {
+                name[..at].to_owned()
+            }
+            _ => name.to_owned(),
+        }
+    }
+
+    /// Whether the fully qualified `name` is the apex or below it.
+    fn contains(&self, name: &str) -> bool {
+        let zone = self.name.to_lowercase();
+        name == zone || name.ends_with(&format!(".{}", zone))
+    }
+}
+
+fn is_zone_id(s: &str) -> bool {
+    s.len() == 32 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn longest_match(zones: &[Zone], record: &str) -> Option<Zone> {
+    zones
+        .iter()
+        .filter(|zone| zone.contains(record))
+        .max_by_key(|zone| zone.name.len())
+        .cloned()
+}
+
+/// How long a listing of the token's zones is trusted.
+const CACHE_SECONDS: u64 = 3600;
+
+/// Every zone one token could see at one time. Only complete listings are
+/// cached, so that a zone missing from one really wasn't visible.
+#[derive(Deserialize, Serialize)]
+struct Listing {
+    key: String,
+    listed_at: u64,
+    zones: Vec<Zone>,
+}
+
+/// Where zone listings are remembered between runs.
+const CACHE_FILE: &str = "zones.json";
+
+/// The zones the token could see when last listed, however long ago.
+pub fn cached(token: &str) -> Vec<Zone> {
+    let key = token::cache_key(token);
+    config::load_cache::<Vec<Listing>>(CACHE_FILE)
+        .into_iter()
+        .find(|l| l.key == key)
+        .map(|l| l.zones)
+        .unwrap_or_default()
+}
+
+fn fresh_listing(token: &str) -> Option<Vec<Zone>> {
+    let key = token::cache_key(token);
+    config::load_cache::<Vec<Listing>>(CACHE_FILE)
+        .into_iter()
+        .find(|l| l.key == key && now().saturating_sub(l.listed_at) < CACHE_SECONDS)
+        .map(|l| l.zones)
+}
+
+fn save_listing(token: &str, zones: &[Zone]) {
+    let key = token::cache_key(token);
+    let mut listings = config::load_cache::<Vec<Listing>>(CACHE_FILE);
+    listings.retain(|l| l.key != key);
+    listings.push(Listing {
+        key,
+        listed_at: now(),
+        zones: zones.to_vec(),
+    });
+    config::save_cache(CACHE_FILE, &listings);
+}
@@ -0,0 +1,634 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use tabwriter::TabWriter;
+
+use crate::data;
+use crate::error::Invalid;
+use crate::record::{Entry, RecordBody, PRIORITY_TYPES, TTL_AUTO};
+use crate::zone::Zone;
+
+/// `$TTL` of exported files, for records added to them by hand.
+pub const DEFAULT_TTL: u32 = 300;
+
+/// Longest string a TXT record may hold in one piece.
+const TXT_CHUNK: usize = 255;
+
+/// Types whose content is a host name, written fully qualified.
+const HOST_TYPES: &[&str] = &["CNAME", "NS", "PTR", "MX"];
+
+/// Write `records` as an RFC 1035 master file, with names relative to the
+/// zone. Records are sorted, and there's no timestamp, so that exporting
+/// an unchanged zone gives the same file.
+///
+/// As in Cloudflare's own export, every record carries its TTL, with `1`
+/// for the automatic TTL so the file imports back unchanged, and proxied
+/// records are marked with a `cf_tags=cf-proxied:true` comment.
+pub fn write(out: &mut impl Write, zone: &Zone, records: &[Entry]) -> Result<()> {
+    let mut records: Vec<_> = records.iter().collect();
+    records.sort_by_cached_key(|entry| {
+        let name = zone.relative(&entry.name);
+        (name != "@", name, entry.r#type.clone(), rdata(entry))
+    });
+
+    writeln!(out, ";; Zone: {}", zone.name)?;
+    writeln!(out, ";; Exported from Cloudflare by cf-record")?;
+    writeln!(out, "$ORIGIN {}", fqdn(&zone.name))?;
+    writeln!(out, "$TTL {}", DEFAULT_TTL)?;
+    writeln!(out)?;
+
+    let mut tw = TabWriter::new(out);
+    for entry in records {
+        write!(
+            &mut tw,
+            "{}\t{}\tIN\t{}\t{}",
+            owner(zone, &entry.name),
+            entry.ttl,
+            entry.r#type,
+            rdata(entry)
+        )?;
+        if entry.proxied {
+            write!(&mut tw, " ; cf_tags=cf-proxied:true")?;
+        }
+        writeln!(&mut tw)?;
+    }
+    tw.flush()?;
+
+    Ok(())
+}
+
+/// The record name relative to the zone, or fully qualified if it's outside.
+fn owner(zone: &Zone, name: &str) -> String {
+    let relative = zone.relative(name);
+    if relative == "@" || !relative.eq_ignore_ascii_case(name) {
+        relative
+    } else {
+        fqdn(name)
+    }
+}
+
+/// The record data in presentation format.
+fn rdata(entry: &Entry) -> String {
+    let r#type = entry.r#type.as_str();
+    match r#type {
+        "TXT" | "SPF" => txt_strings(&entry.content),
+        "MX" => format!("{} {}", entry.priority.unwrap_or(0), fqdn(&entry.content)),
+        _ if HOST_TYPES.contains(&r#type) => fqdn(&entry.content),
+        _ => match &entry.data {
+            Some(Value::Object(data)) if data::is_structured(r#type) => {
+                let mut data = data.clone();
+                // Target host names must be absolute, or they would be read
+                // as relative to $ORIGIN.
+                for key in &["target", "replacement"] {
+                    if let Some(Value::String(host)) = data.get_mut(*key) {
+                        if !host.contains(['/', ':']) && host != "." {
+                            *host = fqdn(host);
+                        }
+                    }
+                }
+                let rendered = data::render(r#type, &Value::Object(data));
+                match entry.priority {
+                    Some(priority) if r#type == "URI" => format!("{} {}", priority, rendered),
+                    _ => rendered,
+                }
+            }
+            _ => entry.display_content(),
+        },
+    }
+}
+
+/// TXT content as quoted strings of at most 255 bytes. Content that is
+/// already quoted, as Cloudflare returns long records, is kept as it is.
+pub fn txt_strings(content: &str) -> String {
+    if content.starts_with('"') && content.ends_with('"') && content.len() > 1 {
+        return content.to_owned();
+    }
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for c in content.chars() {
+        if chunk.len() + c.len_utf8() > TXT_CHUNK {
+            chunks.push(data::quote(&chunk));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(data::quote(&chunk));
+    }
+    chunks.join(" ")
+}
+
+/// `name` with a trailing dot.
+fn fqdn(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_owned()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+/// How deeply `$INCLUDE`s may nest, to stop include loops.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// A record read from a zone file.
+pub struct Parsed {
+    pub body: RecordBody,
+    /// Whether the line was marked `cf_tags=cf-proxied:true` or `false`.
+    pub proxied: Option<bool>,
+    /// Where the record was found, as `file:line`.
+    pub location: String,
+}
+
+/// What a zone file holds: the records to create, and the SOA and apex NS
+/// records left out as Cloudflare manages those itself.
+pub struct ZoneFile {
+    pub records: Vec<Parsed>,
+    pub skipped: Vec<Parsed>,
+}
+
+/// Parse the master file at `path` for `zone`, following `$ORIGIN`, `$TTL`
+/// and `$INCLUDE`. Records without a TTL get `$TTL`, or the automatic TTL
+/// if the file sets none.
+pub fn parse(path: &Path, zone: &Zone) -> Result<ZoneFile> {
+    let mut file = ZoneFile {
+        records: Vec::new(),
+        skipped: Vec::new(),
+    };
+    let mut state = State {
+        origin: zone.name.to_lowercase(),
+        ttl: None,
+        owner: None,
+    };
+    parse_file(path, &mut state, &mut file, 0)?;
+
+    let apex = zone.name.to_lowercase();
+    let (skipped, records): (Vec<_>, Vec<_>) = file
+        .records
+        .into_iter()
+        .partition(|p| p.body.r#type == "SOA" || (p.body.r#type == "NS" && p.body.name == apex));
+    file.records = records;
+    file.skipped = skipped;
+    Ok(file)
+}
+
+struct State {
+    /// Lower case, without the trailing dot.
+    origin: String,
+    ttl: Option<u32>,
+    /// The last owner name, which lines starting with a blank repeat.
+    owner: Option<String>,
+}
+
+fn parse_file(path: &Path, state: &mut State, file: &mut ZoneFile, depth: usize) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(Invalid(format!("$INCLUDE nested too deeply at {}", path.display())).into());
+    }
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read zone file {}", path.display()))?;
+
+    for (number, line, comment) in logical_lines(&text, path)? {
+        let location = format!("{}:{}", path.display(), number);
+        let invalid = |msg: String| Invalid(format!("{}: {}", location, msg));
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if line.starts_with('$') {
+            let (directive, rest) = next_word(&line).unwrap_or_default();
+            let (arg, rest) = next_word(rest).unwrap_or_default();
+            match directive.to_uppercase().as_str() {
+                "$ORIGIN" => state.origin = absolute(arg, &state.origin),
+                "$TTL" => {
+                    state.ttl = Some(
+                        parse_ttl(arg).ok_or_else(|| invalid(format!("Invalid $TTL {}", arg)))?,
+                    )
+                }
+                "$INCLUDE" => {
+                    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+                    let mut included = State {
+                        origin: match next_word(rest) {
+                            Some((origin, _)) => absolute(origin, &state.origin),
+                            None => state.origin.clone(),
+                        },
+                        ttl: state.ttl,
+                        owner: state.owner.clone(),
+                    };
+                    parse_file(&dir.join(arg), &mut included, file, depth + 1)?;
+                }
+                _ => return Err(invalid(format!("Unknown directive {}", directive)).into()),
+            }
+            continue;
+        }
+
+        // A line starting with a blank belongs to the previous owner.
+        let mut rest = line.as_str();
+        let owner = if line.starts_with([' ', '\t']) {
+            state
+                .owner
+                .clone()
+                .ok_or_else(|| invalid("No owner name for the record".to_owned()))?
+        } else {
+            let (owner, after) = next_word(rest).unwrap_or_default();
+            rest = after;
+            absolute(owner, &state.origin)
+        };
+        state.owner = Some(owner.clone());
+
+        // TTL and class may come in either order, and both are optional.
+        let mut ttl = None;
+        let r#type = loop {
+            let (word, after) =
+                next_word(rest).ok_or_else(|| invalid("Missing record type".to_owned()))?;
+            rest = after;
+            if ttl.is_none() && word.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl =
+                    Some(parse_ttl(word).ok_or_else(|| invalid(format!("Invalid TTL {}", word)))?);
+            } else if ["IN", "CH", "HS"].contains(&word.to_uppercase().as_str()) {
+                if !word.eq_ignore_ascii_case("IN") {
+                    return Err(invalid(format!("Only class IN is supported, not {}", word)).into());
+                }
+            } else {
+                break word.to_uppercase();
+            }
+        };
+
+        let mut body = RecordBody::new(&owner, &r#type);
+        body.ttl = ttl.or(state.ttl).unwrap_or(TTL_AUTO);
+        if r#type != "SOA" {
+            set_rdata(&mut body, rest.trim(), &state.origin)
+                .map_err(|err| invalid(format!("{:#}", err)))?;
+        }
+        file.records.push(Parsed {
+            body,
+            proxied: comment
+                .split_whitespace()
+                .find_map(|word| word.strip_prefix("cf_tags="))
+                .and_then(|tags| {
+                    tags.split(',')
+                        .find_map(|tag| tag.strip_prefix("cf-proxied:"))
+                })
+                .map(|proxied| proxied == "true"),
+            location,
+        });
+    }
+    Ok(())
+}
+
+/// Fill in the content, priority or data of `body` from presentation format.
+fn set_rdata(body: &mut RecordBody, rdata: &str, origin: &str) -> Result<()> {
+    let r#type = body.r#type.clone();
+    let tokens = data::tokenize(rdata)?;
+    match r#type.as_str() {
+        // Cloudflare splits long TXT records into strings again itself.
+        "TXT" | "SPF" => body.content = tokens.concat(),
+        "CNAME" | "NS" | "PTR" | "DNAME" => {
+            body.content = absolute(tokens.first().map_or("", |t| t.as_str()), origin)
+        }
+        _ if PRIORITY_TYPES.contains(&r#type.as_str()) => {
+            let (priority, rest) = tokens
+                .split_first()
+                .ok_or_else(|| Invalid(format!("{} record has no priority", r#type)))?;
+            body.priority = Some(
+                priority
+                    .parse()
+                    .map_err(|_| Invalid(format!("Invalid priority {}", priority)))?,
+            );
+            if r#type == "MX" {
+                body.content = absolute(rest.first().map_or("", |t| t.as_str()), origin);
+            } else {
+                let rest: Vec<_> = rest.iter().map(|t| data::quote(t)).collect();
+                body.data = Some(Value::Object(data::parse(&r#type, &rest.join(" "))?));
+            }
+        }
+        _ if data::is_structured(&r#type) => {
+            let mut fields = data::parse(&r#type, rdata)?;
+            for key in &["target", "replacement"] {
+                if let Some(Value::String(host)) = fields.get_mut(*key) {
+                    if !host.contains(['/', ':']) && host != "." {
+                        *host = absolute(host, origin);
+                    }
+                }
+            }
+            body.data = Some(Value::Object(fields));
+        }
+        _ => body.content = tokens.join(" "),
+    }
+    Ok(())
+}
+
+/// Lines of the file with comments removed and parenthesised groups joined,
+/// along with the line number they started on and their comments.
+fn logical_lines(text: &str, path: &Path) -> Result<Vec<(usize, String, String)>> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut comment = String::new();
+    let mut start = 0;
+    let mut depth = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        if depth == 0 {
+            start = i + 1;
+        }
+        let mut in_quote = false;
+        let mut escaped = false;
+        for (at, c) in line.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quote = !in_quote,
+                ';' if !in_quote => {
+                    comment.push_str(&line[at + 1..]);
+                    comment.push(' ');
+                    break;
+                }
+                '(' if !in_quote => {
+                    depth += 1;
+                    current.push(' ');
+                    continue;
+                }
+                ')' if !in_quote => {
+                    if depth == 0 {
+                        return Err(Invalid(format!(
+                            "{}:{}: Unbalanced parenthesis",
+                            path.display(),
+                            i + 1
+                        ))
+                        .into());
+                    }
+                    depth -= 1;
+                    current.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(c);
+        }
+        if depth == 0 {
+            lines.push((start, current.clone(), comment.clone()));
+            current.clear();
+            comment.clear();
+        } else {
+            current.push(' ');
+        }
+    }
+    if depth != 0 {
+        return Err(Invalid(format!(
+            "{}:{}: Unclosed parenthesis",
+            path.display(),
+            start
+        ))
+        .into());
+    }
+    Ok(lines)
+}
+
+/// Split off the first whitespace separated word.
+fn next_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return None;
+    }
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    Some((&s[..end], &s[end..]))
+}
+
+/// Resolve a name from the file against `origin`, giving the lower case
+/// fully qualified name without the trailing dot.
+fn absolute(name: &str, origin: &str) -> String {
+    let name = name.to_lowercase();
+    if name == "@" {
+        origin.to_owned()
+    } else if let Some(name) = name.strip_suffix('.') {
+        name.to_owned()
+    } else if origin.is_empty() {
+        name
+    } else {
+        format!("{}.{}", name, origin)
+    }
+}
+
+/// A TTL in seconds, or with BIND's units as in `1h30m`.
+fn parse_ttl(s: &str) -> Option<u32> {
+    if let Ok(ttl) = s.parse() {
+        return Some(ttl);
+    }
+    let mut total: u32 = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        let n: u32 = number.parse().ok()?;
+        total = total.checked_add(n.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if number.is_empty() {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+/// TXT content as the single string it stands for, whether Cloudflare has
+/// it quoted and split or not.
+pub fn unquote_txt(content: &str) -> String {
+    if content.starts_with('"') {
+        if let Ok(tokens) = data::tokenize(content) {
+            return tokens.concat();
+        }
+    }
+    content.to_owned()
+}
+
+/// Whether `entry` already holds what `body` would create.
+pub fn same_record(entry: &Entry, body: &RecordBody) -> bool {
+    entry.name.eq_ignore_ascii_case(&body.name)
+        && entry.r#type == body.r#type
+        && match body.r#type.as_str() {
+            "TXT" | "SPF" => unquote_txt(&entry.content) == body.content,
+            _ => entry
+                .display_content()
+                .eq_ignore_ascii_case(&body.display_content()),
+        }
+}
+
+/// Check a parsed record is one Cloudflare would accept.
+pub fn validate(parsed: &Parsed) -> Result<()> {
+    parsed
+        .body
+        .validate()
+        .with_context(|| format!("{}: Cannot import record", parsed.location))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn zone() -> Zone {
+        serde_json::from_value(
+            json!({"id": "023e105f4ecef8ad9ca31a8372d0c353", "name": "example.com"}),
+        )
+        .unwrap()
+    }
+
+    /// A fresh directory holding `files`, for `parse` to read.
+    fn dir(name: &str, files: &[(&str, &str)]) -> TestDir {
+        let dir = TestDir::new(&format!("zonefile-{}", name));
+        for (file, text) in files {
+            fs::write(dir.join(file), text).unwrap();
+        }
+        dir
+    }
+
+    fn parse_text(name: &str, text: &str) -> ZoneFile {
+        let dir = dir(name, &[("zone", text)]);
+        parse(&dir.join("zone"), &zone()).unwrap()
+    }
+
+    #[test]
+    fn parentheses_and_comments() {
+        let file = parse_text(
+            "parens",
+            "@ IN SOA ns1 hostmaster (\n  1 ; serial\n  7200 3600 1209600 3600 )\n\
+             txt IN TXT ( \"v=spf1 \" ; first\n  \"-all\" ) ; last\n\
+             www 300 IN A 203.0.113.10 ; cf_tags=cf-proxied:true\n",
+        );
+        assert_eq!(file.skipped.len(), 1);
+        assert_eq!(file.records.len(), 2);
+        let txt = &file.records[0];
+        assert_eq!(txt.body.name, "txt.example.com");
+        assert_eq!(txt.body.content, "v=spf1 -all");
+        assert!(txt.location.ends_with("zone:4"));
+        assert_eq!(txt.proxied, None);
+        assert_eq!(file.records[1].proxied, Some(true));
+    }
+
+    #[test]
+    fn quoted_semicolons_and_parentheses_are_data() {
+        let file = parse_text("quoted", "@ TXT \"a;b (c)\"\n");
+        assert_eq!(file.records[0].body.content, "a;b (c)");
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        let dir = dir(
+            "unbalanced",
+            &[
+                ("open", "www A ( 203.0.113.10\n"),
+                ("close", "www A 203.0.113.10 )\n"),
+            ],
+        );
+        assert!(parse(&dir.join("open"), &zone()).is_err());
+        assert!(parse(&dir.join("close"), &zone()).is_err());
+    }
+
+    #[test]
+    fn origin_and_include() {
+        let dir = dir(
+            "include",
+            &[
+                (
+                    "zone",
+                    "$ORIGIN dev.example.com.\napi A 203.0.113.1\n\
+                     $INCLUDE inc mail.example.com.\n\
+                     \tAAAA 2001:db8::1\n\
+                     $ORIGIN example.com.\n@ NS ns1.example.net.\nmx MX 10 mail\n",
+                ),
+                ("inc", "@ A 203.0.113.2\nsmtp CNAME @\n"),
+            ],
+        );
+        let file = parse(&dir.join("zone"), &zone()).unwrap();
+        let records: Vec<_> = file
+            .records
+            .iter()
+            .map(|p| {
+                (
+                    p.body.name.as_str(),
+                    p.body.r#type.as_str(),
+                    p.body.display_content(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                ("api.dev.example.com", "A", "203.0.113.1".to_owned()),
+                ("mail.example.com", "A", "203.0.113.2".to_owned()),
+                (
+                    "smtp.mail.example.com",
+                    "CNAME",
+                    "mail.example.com".to_owned()
+                ),
+                // The owner carries on from before the include.
+                ("api.dev.example.com", "AAAA", "2001:db8::1".to_owned()),
+                ("mx.example.com", "MX", "10 mail.example.com".to_owned()),
+            ]
+        );
+        assert_eq!(file.skipped.len(), 1);
+        assert_eq!(file.skipped[0].body.r#type, "NS");
+    }
+
+    #[test]
+    fn ttls() {
+        let file = parse_text("ttls", "a A 203.0.113.1\n$TTL 1h\nb A 203.0.113.2\nc 1d IN A 203.0.113.3\nd IN 1w2d A 203.0.113.4\n");
+        let ttls: Vec<_> = file.records.iter().map(|p| p.body.ttl).collect();
+        assert_eq!(ttls, vec![TTL_AUTO, 3600, 86400, 777600]);
+    }
+
+    #[test]
+    fn ttl_units() {
+        assert_eq!(parse_ttl("300"), Some(300));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("2D"), Some(172800));
+        assert_eq!(parse_ttl("1w"), Some(604800));
+        assert_eq!(parse_ttl("10"), Some(10));
+        assert_eq!(parse_ttl("1h30"), None);
+        assert_eq!(parse_ttl("1y"), None);
+        assert_eq!(parse_ttl("99999999w"), None);
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let long = "k".repeat(300);
+        let records: Vec<Entry> = serde_json::from_value(json!([
+            {"id": "1", "name": "example.com", "type": "A", "content": "203.0.113.10", "ttl": 1, "proxied": true},
+            {"id": "2", "name": "txt.example.com", "type": "TXT", "content": format!("v=DKIM1; p={}", long), "ttl": 1},
+            {"id": "3", "name": "example.com", "type": "MX", "content": "mail.example.com", "priority": 10, "ttl": 3600},
+            {"id": "4", "name": "www.example.com", "type": "CNAME", "content": "example.com", "ttl": 300},
+            {"id": "5", "name": "example.com", "type": "CAA", "content": "0 issue \"letsencrypt.org\"",
+             "data": {"flags": 0, "tag": "issue", "value": "letsencrypt.org"}, "ttl": 1},
+        ]))
+        .unwrap();
+
+        let mut out = Vec::new();
+        write(&mut out, &zone(), &records).unwrap();
+        let file = parse_text("round-trip", &String::from_utf8(out).unwrap());
+
+        assert_eq!(file.records.len(), records.len());
+        for entry in &records {
+            let parsed = file
+                .records
+                .iter()
+                .find(|p| same_record(entry, &p.body))
+                .unwrap_or_else(|| panic!("{} {} did not round-trip", entry.name, entry.r#type));
+            assert_eq!(parsed.body.ttl, entry.ttl, "TTL of {}", entry.name);
+            assert_eq!(parsed.proxied.unwrap_or(false), entry.proxied);
+            validate(parsed).unwrap();
+        }
+    }
+}